@@ -15,40 +15,236 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use std::f64::consts::PI;
+use std::cmp::{min, max};
+use std::cell::RefCell;
+use std::collections::HashMap;
 
-use cairo::{Context, RadialGradient};
+use time::{Duration, SteadyTime};
 
-use shakmaty::{Color, Square, Role, Bitboard, Chess, Position, Move, MoveList};
+use cairo::{Context, Format, ImageSurface, Matrix, RadialGradient};
+
+use shakmaty::{Color, Square, File, Rank, Piece, Role, Bitboard, Board, Chess, Position, Move, MoveList};
 
 use pieceset::PieceSet;
-use util::{file_to_float, rank_to_float};
+use drawable::DrawBrush;
+use util::{contrast, ease, file_to_float, rank_to_float};
+use ground::WidgetContext;
+
+/// Placement of the coordinate labels.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CoordinatesStyle {
+    /// Labels on all four sides of the border around the board.
+    Border,
+    /// Labels inside the edge squares, like on lichess: files in the
+    /// bottom right corner of the bottom rank and ranks in the top left
+    /// corner of the left file, as seen from the current orientation.
+    Inline,
+}
+
+/// What to show about the material of each side in the border.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum MaterialBalance {
+    /// Nothing.
+    Hidden,
+    /// The material advantage in pawns, like `+3`, next to the side that
+    /// is ahead.
+    Difference,
+    /// The pieces each side has captured in excess of the other side,
+    /// followed by the material advantage.
+    Pieces,
+}
+
+/// How the board turns around when its orientation changes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FlipAnimation {
+    /// Flip at once, without animation.
+    Instant,
+    /// Rotate by half a turn clockwise.
+    Clockwise,
+    /// Rotate by half a turn counterclockwise.
+    Counterclockwise,
+    /// Flip over like a card, around the horizontal axis.
+    HorizontalAxis,
+    /// Flip over like a card, around the vertical axis.
+    VerticalAxis,
+}
+
+/// Colors of the board and its highlights.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoardTheme {
+    /// Border around the board.
+    pub border: (f64, f64, f64),
+    /// Coordinate labels in the border.
+    pub coordinates: (f64, f64, f64),
+    /// Light squares.
+    pub light: (f64, f64, f64),
+    /// Dark squares.
+    pub dark: (f64, f64, f64),
+    /// Optional thin lines between the squares.
+    pub grid_lines: Option<(f64, f64, f64, f64)>,
+    /// Highlight of the last move.
+    pub last_move: (f64, f64, f64, f64),
+    /// Center of the glow around a king in check.
+    pub check: (f64, f64, f64),
+    /// Highlight of the selected square.
+    pub selected: (f64, f64, f64, f64),
+    /// Legal move hints of the selected piece.
+    pub move_hint: (f64, f64, f64, f64),
+    /// Optional outline drawn around each piece.
+    pub piece_outline: Option<(f64, f64, f64, f64)>,
+}
+
+impl BoardTheme {
+    /// A theme with pure black and white squares, outlined pieces and
+    /// more opaque highlights, for low vision users.
+    pub fn high_contrast() -> BoardTheme {
+        BoardTheme {
+            border: (0.0, 0.0, 0.0),
+            coordinates: (1.0, 1.0, 1.0),
+            light: (1.0, 1.0, 1.0),
+            dark: (0.0, 0.0, 0.0),
+            grid_lines: None,
+            last_move: (1.0, 0.84, 0.0, 0.8),
+            check: (1.0, 0.0, 0.0),
+            selected: (0.0, 0.8, 0.0, 0.8),
+            move_hint: (0.0, 0.8, 0.0, 0.9),
+            piece_outline: Some((0.5, 0.5, 0.5, 1.0)),
+        }
+    }
+}
 
+impl Default for BoardTheme {
+    fn default() -> BoardTheme {
+        BoardTheme {
+            border: (0.2, 0.2, 0.5),
+            coordinates: (0.8, 0.8, 0.8),
+            light: (0.87, 0.89, 0.90),
+            dark: (0.55, 0.64, 0.68),
+            grid_lines: None,
+            last_move: (0.61, 0.78, 0.0, 0.41),
+            check: (1.0, 0.0, 0.0),
+            selected: (0.08, 0.47, 0.11, 0.5),
+            move_hint: (0.08, 0.47, 0.11, 0.5),
+            piece_outline: None,
+        }
+    }
+}
+
+/// Render a static board with its pieces into a square image with the
+/// given side length.
+///
+/// Unlike a `Ground` widget this has no state or interaction, so it is
+/// cheap enough to render many small thumbnails, for example for a list of
+/// puzzles.
+pub fn render_board_thumbnail(board: &Board, size: i32, orientation: Color, theme: &BoardTheme) -> Result<ImageSurface, cairo::Error> {
+    let state = BoardState::empty(orientation, theme.clone(), SteadyTime::now());
+
+    let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+
+    {
+        let cr = Context::new(&surface)?;
+        cr.set_matrix(state.matrix(0, 0, size, size));
+        state.draw(&cr, false)?;
+
+        for (square, piece) in board.clone() {
+            cr.save()?;
+            cr.translate(0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank()));
+            cr.rotate(orientation.fold_wb(0.0, PI));
+            cr.translate(-0.5, -0.5);
+            state.draw_piece(&cr, &piece)?;
+            cr.restore()?;
+        }
+    }
+
+    Ok(surface)
+}
+
+#[derive(Clone)]
 pub struct BoardState {
     orientation: Color,
+    orientation_locked: bool,
+    coordinates_style: CoordinatesStyle,
+    show_coordinates: bool,
     check: Option<Square>,
+    checkers: Bitboard,
+    highlights: HashMap<Square, (f64, f64, f64, f64)>,
     last_move: Option<(Square, Square)>,
+    last_move_extra: Bitboard,
+    last_move_fade: Option<Duration>,
+    last_move_since: SteadyTime,
+    last_move_elapsed: f64,
     turn: Option<Color>,
+    previous_turn: Option<Color>,
+    turn_animation: bool,
+    turn_since: SteadyTime,
+    turn_elapsed: f64,
+    flip_animation: FlipAnimation,
+    flip_duration: Option<f64>,
+    animated: bool,
+    flip_since: SteadyTime,
+    flip_elapsed: f64,
+    theme: BoardTheme,
     piece_set: PieceSet,
+    pixel_align: bool,
+    coordinates_min_contrast: Option<f64>,
+    material: MaterialBalance,
     legals: MoveList,
+    cache: RefCell<Option<BoardCache>>,
+}
+
+/// The static border and squares, rendered in device space.
+#[derive(Clone)]
+struct BoardCache {
+    matrix: Matrix,
+    scale: (f64, f64),
+    x: f64,
+    y: f64,
+    surface: ImageSurface,
 }
 
 impl BoardState {
-    pub fn new() -> Self {
-        BoardState::from_position(&Chess::default())
+    pub fn new(now: SteadyTime) -> Self {
+        BoardState::from_position(&Chess::default(), now)
     }
 
-    pub fn from_position<P: Position>(pos: &P) -> Self {
-        let mut state = BoardState {
-            orientation: pos.turn(),
+    pub fn from_position<P: Position>(pos: &P, now: SteadyTime) -> Self {
+        let mut state = BoardState::empty(pos.turn(), BoardTheme::default(), now);
+        state.set_position(pos);
+        state
+    }
+
+    fn empty(orientation: Color, theme: BoardTheme, now: SteadyTime) -> Self {
+        BoardState {
+            orientation,
+            orientation_locked: false,
+            coordinates_style: CoordinatesStyle::Border,
+            show_coordinates: true,
             check: None,
+            checkers: Bitboard(0),
+            highlights: HashMap::new(),
             last_move: None,
+            last_move_extra: Bitboard(0),
+            last_move_fade: None,
+            last_move_since: now,
+            last_move_elapsed: 0.0,
             turn: None,
-            piece_set: PieceSet::merida(),
+            previous_turn: None,
+            turn_animation: false,
+            turn_since: now,
+            turn_elapsed: 1.0,
+            flip_animation: FlipAnimation::Instant,
+            flip_duration: Some(400.0),
+            animated: true,
+            flip_since: now,
+            flip_elapsed: 1.0,
+            theme,
+            piece_set: PieceSet::default(),
+            pixel_align: false,
+            coordinates_min_contrast: None,
+            material: MaterialBalance::Hidden,
             legals: MoveList::new(),
-        };
-
-        state.set_position(pos);
-        state
+            cache: RefCell::new(None),
+        }
     }
 
     pub fn set_position<P: Position>(&mut self, pos: &P) {
@@ -57,16 +253,58 @@ impl BoardState {
         self.turn = Some(pos.turn());
     }
 
-    pub fn set_last_move(&mut self, m: Option<(Square, Square)>) {
-        self.last_move = m;
+    /// Set the last move, with `extra` squares that are highlighted as
+    /// part of it, like the rook squares of castling.
+    pub fn set_last_move(&mut self, m: Option<(Square, Square)>, extra: Bitboard, now: SteadyTime) {
+        if m != self.last_move || extra != self.last_move_extra {
+            self.last_move = m;
+            self.last_move_extra = extra;
+            self.last_move_since = now;
+            self.last_move_elapsed = 0.0;
+        }
+    }
+
+    pub fn last_move(&self) -> Option<(Square, Square)> {
+        self.last_move
+    }
+
+    pub fn last_move_extra(&self) -> Bitboard {
+        self.last_move_extra
+    }
+
+    pub fn set_last_move_fade(&mut self, fade: Option<Duration>) {
+        self.last_move_fade = fade;
     }
 
     pub fn set_check(&mut self, king: Option<Square>) {
         self.check = king;
     }
 
-    pub fn set_turn(&mut self, turn: Option<Color>) {
-        self.turn = turn;
+    pub fn check(&self) -> Option<Square> {
+        self.check
+    }
+
+    pub fn set_checkers(&mut self, checkers: Bitboard) {
+        self.checkers = checkers;
+    }
+
+    pub fn set_highlights(&mut self, highlights: Vec<(Square, DrawBrush)>) {
+        self.highlights = highlights.into_iter()
+            .map(|(square, brush)| (square, brush.rgba(0.4)))
+            .collect();
+    }
+
+    pub fn set_turn(&mut self, turn: Option<Color>, now: SteadyTime) {
+        if turn != self.turn {
+            self.previous_turn = self.turn;
+            self.turn = turn;
+            self.turn_since = now;
+            self.turn_elapsed = if self.animated { 0.0 } else { 1.0 };
+        }
+    }
+
+    pub fn set_turn_animation(&mut self, turn_animation: bool) {
+        self.turn_animation = turn_animation;
     }
 
     pub fn turn(&self) -> Option<Color> {
@@ -95,34 +333,284 @@ impl BoardState {
         &mut self.legals
     }
 
-    pub fn set_orientation(&mut self, orientation: Color) {
-        self.orientation = orientation;
+    pub fn set_orientation(&mut self, orientation: Color, now: SteadyTime) {
+        if orientation != self.orientation && !self.orientation_locked {
+            self.orientation = orientation;
+            if self.flip_animation != FlipAnimation::Instant && self.animated && self.flip_duration.is_some() {
+                self.flip_since = now;
+                self.flip_elapsed = 0.0;
+            }
+        }
+    }
+
+    pub fn settle(&mut self) {
+        self.last_move_elapsed = 1.0;
+        self.turn_elapsed = 1.0;
+        self.flip_elapsed = 1.0;
+    }
+
+    pub fn set_flip_animation(&mut self, flip_animation: FlipAnimation) {
+        self.flip_animation = flip_animation;
+        self.flip_elapsed = 1.0;
+    }
+
+    /// Set how long a flip takes, or `None` to always flip at once.
+    pub fn set_flip_duration(&mut self, duration: Option<Duration>) {
+        self.flip_duration = duration.map(|d| d.num_milliseconds() as f64);
+        if self.flip_duration.is_none() {
+            self.flip_elapsed = 1.0;
+        }
+    }
+
+    /// Flip at once and move the turn indicator without a transition while
+    /// animations are disabled, regardless of the flip duration.
+    pub fn set_animated(&mut self, animated: bool) {
+        self.animated = animated;
+        if !animated {
+            self.flip_elapsed = 1.0;
+            self.turn_elapsed = 1.0;
+        }
+    }
+
+    pub fn set_orientation_locked(&mut self, locked: bool) {
+        self.orientation_locked = locked;
     }
 
     pub fn orientation(&self) -> Color {
         self.orientation
     }
 
+    pub fn set_coordinates_style(&mut self, style: CoordinatesStyle) {
+        self.coordinates_style = style;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_show_coordinates(&mut self, show_coordinates: bool) {
+        self.show_coordinates = show_coordinates;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_coordinates_min_contrast(&mut self, min_contrast: Option<f64>) {
+        self.coordinates_min_contrast = min_contrast;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_material(&mut self, material: MaterialBalance) {
+        self.material = material;
+    }
+
+    pub fn set_theme(&mut self, theme: BoardTheme) {
+        self.theme = theme;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_grid_lines(&mut self, grid_lines: Option<(f64, f64, f64, f64)>) {
+        self.theme.grid_lines = grid_lines;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_piece_outline(&mut self, outline: Option<(f64, f64, f64, f64)>) {
+        self.theme.piece_outline = outline;
+    }
+
+    pub fn theme(&self) -> &BoardTheme {
+        &self.theme
+    }
+
     pub fn piece_set(&self) -> &PieceSet {
         &self.piece_set
     }
 
-    pub(crate) fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
-        self.draw_border(cr)?;
+    pub fn set_piece_set(&mut self, piece_set: PieceSet) {
+        self.piece_set = piece_set;
+        *self.cache.get_mut() = None;
+    }
+
+    pub fn set_pixel_align(&mut self, pixel_align: bool) {
+        self.pixel_align = pixel_align;
+    }
+
+    /// Transformation from board coordinates to a rectangle, with the
+    /// board centered in it.
+    pub fn matrix(&self, x: i32, y: i32, width: i32, height: i32) -> Matrix {
+        let size = max(min(width, height), 9);
+
+        let mut matrix = Matrix::identity();
+        matrix.translate(f64::from(x), f64::from(y));
+
+        if self.pixel_align {
+            // whole pixel squares around a whole pixel center, so that all
+            // square edges fall on pixel boundaries
+            matrix.translate(f64::from(width / 2), f64::from(height / 2));
+            matrix.scale(f64::from(size / 9), f64::from(size / 9));
+        } else {
+            matrix.translate(f64::from(width) / 2.0, f64::from(height) / 2.0);
+            matrix.scale(f64::from(size) / 9.0, f64::from(size) / 9.0);
+        }
+
+        // squash the board towards the axis while flipping it over
+        let t = ease(0.0, 1.0, self.flip_elapsed);
+        let squash = (PI * t).cos().abs().max(0.01);
+        match self.flip_animation {
+            FlipAnimation::HorizontalAxis if t < 1.0 => matrix.scale(1.0, squash),
+            FlipAnimation::VerticalAxis if t < 1.0 => matrix.scale(squash, 1.0),
+            _ => {}
+        }
+
+        matrix.rotate(self.angle());
+        matrix.translate(-4.0, -4.0);
+
+        matrix
+    }
+
+    /// Rotation of the board, in the middle of a flip animation if one is
+    /// running.
+    fn angle(&self) -> f64 {
+        let angle = self.orientation.fold_wb(0.0, PI);
+        let t = ease(0.0, 1.0, self.flip_elapsed);
+        match self.flip_animation {
+            _ if t >= 1.0 => angle,
+            FlipAnimation::Instant => angle,
+            FlipAnimation::Clockwise => angle - PI * (1.0 - t),
+            FlipAnimation::Counterclockwise => angle + PI * (1.0 - t),
+            FlipAnimation::HorizontalAxis | FlipAnimation::VerticalAxis => {
+                // show the back side until the board stands on its edge
+                if t < 0.5 { angle + PI } else { angle }
+            }
+        }
+    }
+
+    /// Rotation that keeps pieces and text upright on the board.
+    pub(crate) fn upright(&self) -> f64 {
+        -self.angle()
+    }
+
+    /// Render a piece into the unit square at the origin.
+    pub(crate) fn draw_piece(&self, cr: &Context, piece: &Piece) -> Result<(), cairo::Error> {
+        if let Some((r, g, b, a)) = self.theme.piece_outline {
+            // stamp the silhouette of the piece around it
+            cr.push_group();
+            self.piece_set.render(cr, piece)?;
+            let silhouette = cr.pop_group()?;
+
+            let width = 0.025;
+            cr.set_source_rgba(r, g, b, a);
+            for &(dx, dy) in &[(-width, 0.0), (width, 0.0), (0.0, -width), (0.0, width)] {
+                cr.save()?;
+                cr.translate(dx, dy);
+                cr.mask(&silhouette)?;
+                cr.restore()?;
+            }
+        }
+
+        self.piece_set.render(cr, piece)
+    }
+
+    pub(crate) fn queue_animation(&mut self, ctx: &WidgetContext) {
+        if self.flip_elapsed < 1.0 {
+            ctx.queue_draw();
+            let total = self.flip_duration.unwrap_or(1.0).max(1.0);
+            self.flip_elapsed = ((ctx.now() - self.flip_since).num_milliseconds() as f64 / total).min(1.0);
+        }
+
+        if self.turn_animation && self.turn_elapsed < 1.0 {
+            ctx.queue_draw_rect(8.0, -0.5, 0.5, 9.0);
+            self.turn_elapsed = ((ctx.now() - self.turn_since).num_milliseconds() as f64 / 300.0).min(1.0);
+        }
+
+        if let (Some((orig, dest)), Some(fade)) = (self.last_move, self.last_move_fade) {
+            if self.last_move_elapsed < 1.0 {
+                ctx.queue_draw_square(orig);
+                ctx.queue_draw_square(dest);
+                for square in self.last_move_extra {
+                    ctx.queue_draw_square(square);
+                }
+
+                let total = fade.num_milliseconds().max(1) as f64;
+                self.last_move_elapsed = ((ctx.now() - self.last_move_since).num_milliseconds() as f64 / total).min(1.0);
+            }
+        }
+    }
+
+    /// Draw the board. Offscreen renderings pass `cached: false`, so that
+    /// they neither use nor replace the surface cached for the widget.
+    pub(crate) fn draw(&self, cr: &Context, cached: bool) -> Result<(), cairo::Error> {
+        if cached {
+            self.draw_cached(cr)?;
+        } else {
+            self.draw_border(cr)?;
+            self.draw_board(cr)?;
+        }
         self.draw_turn(cr)?;
-        self.draw_board(cr)?;
         self.draw_last_move(cr)?;
+        self.draw_highlights(cr)?;
         self.draw_check(cr)?;
+        self.draw_inline_coordinates(cr)?;
+        Ok(())
+    }
+
+    fn draw_cached(&self, cr: &Context) -> Result<(), cairo::Error> {
+        // do not cache frames of a flip
+        if self.flip_elapsed < 1.0 {
+            self.draw_border(cr)?;
+            return self.draw_board(cr);
+        }
+
+        // the border and squares only change with the theme, the size or
+        // the orientation, so keep them around while pieces are animated
+        let matrix = cr.matrix();
+        let scale = cr.target().device_scale();
+        let mut cache = self.cache.borrow_mut();
+
+        if cache.as_ref().map_or(true, |c| c.matrix != matrix || c.scale != scale) {
+            let (x1, y1) = matrix.transform_point(-0.5, -0.5);
+            let (x2, y2) = matrix.transform_point(8.5, 8.5);
+            let (x, y) = (x1.min(x2).floor(), y1.min(y2).floor());
+            let width = (x1.max(x2).ceil() - x) as i32;
+            let height = (y1.max(y2).ceil() - y) as i32;
+
+            // render at the resolution of the target, for example on
+            // HiDPI screens
+            let (sx, sy) = scale;
+            let surface = ImageSurface::create(Format::ARgb32,
+                                               max((f64::from(width) * sx).ceil() as i32, 1),
+                                               max((f64::from(height) * sy).ceil() as i32, 1))?;
+            surface.set_device_scale(sx, sy);
+
+            {
+                let layer = Context::new(&surface)?;
+                layer.set_matrix(Matrix::multiply(&matrix, &Matrix::new(1.0, 0.0, 0.0, 1.0, -x, -y)));
+                self.draw_border(&layer)?;
+                self.draw_board(&layer)?;
+            }
+
+            *cache = Some(BoardCache { matrix, scale, x, y, surface });
+        }
+
+        if let Some(ref cache) = *cache {
+            cr.save()?;
+            cr.identity_matrix();
+            cr.set_source_surface(&cache.surface, cache.x, cache.y)?;
+            cr.paint()?;
+            cr.restore()?;
+        }
+
         Ok(())
     }
 
     fn draw_border(&self, cr: &Context) -> Result<(), cairo::Error> {
-        cr.set_source_rgb(0.2, 0.2, 0.5);
+        let (r, g, b) = self.theme.border;
+        cr.set_source_rgb(r, g, b);
         cr.rectangle(-0.5, -0.5, 9.0, 9.0);
         cr.fill()?;
 
+        if !self.show_coordinates || self.coordinates_style != CoordinatesStyle::Border {
+            return Ok(());
+        }
+
         cr.set_font_size(0.20);
-        cr.set_source_rgb(0.8, 0.8, 0.8);
+        let (r, g, b) = self.readable(self.theme.coordinates, self.theme.border);
+        cr.set_source_rgb(r, g, b);
 
         for (rank, glyph) in ["1", "2", "3", "4", "5", "6", "7", "8"].iter().enumerate() {
             self.draw_text(cr, (-0.25, 7.5 - rank as f64), glyph)?;
@@ -137,21 +625,137 @@ impl BoardState {
         Ok(())
     }
 
-    fn draw_turn(&self, cr: &Context) -> Result<(), cairo::Error> {
-        match self.turn {
-            Some(Color::White) => {
-                cr.set_source_rgb(1.0, 1.0, 1.0);
-                cr.arc(8.25, 8.25, 0.1, 0.0, 2.0 * PI);
-                cr.fill()?;
-            },
-            Some(Color::Black) => {
-                cr.set_source_rgb(0.0, 0.0, 0.0);
-                cr.arc(8.25, -0.25, 0.1, 0.0, 2.0 * PI);
-                cr.fill()?;
+    /// Show the material balance of the board in the border strips next
+    /// to the home rank of each side.
+    pub(crate) fn draw_material(&self, cr: &Context, board: &Board) -> Result<(), cairo::Error> {
+        if self.material == MaterialBalance::Hidden {
+            return Ok(());
+        }
+
+        let roles = [(Role::Queen, 9), (Role::Rook, 5), (Role::Bishop, 3), (Role::Knight, 3), (Role::Pawn, 1)];
+        let count = |color: Color, role: Role| (board.by_color(color) & board.by_role(role)).count() as i32;
+
+        // from left to right, as seen by the viewer
+        let (start, step) = self.orientation.fold_wb((0.25, 0.3), (7.75, -0.3));
+
+        cr.save()?;
+        cr.set_font_size(0.20);
+
+        for &color in &[Color::White, Color::Black] {
+            let y = color.fold_wb(8.25, -0.25);
+            let mut x = start;
+            let mut advantage = 0;
+
+            for &(role, value) in &roles {
+                let surplus = count(color, role) - count(!color, role);
+                advantage += surplus * value;
+
+                if self.material == MaterialBalance::Pieces {
+                    // show the captured pieces of the opponent
+                    for _ in 0..surplus {
+                        cr.save()?;
+                        cr.translate(x, y);
+                        cr.rotate(self.upright());
+                        cr.translate(-0.2, -0.2);
+                        cr.scale(0.4, 0.4);
+                        self.draw_piece(cr, &role.of(!color))?;
+                        cr.restore()?;
+                        x += step;
+                    }
+                }
+            }
+
+            if advantage > 0 {
+                let (r, g, b) = self.readable(self.theme.coordinates, self.theme.border);
+                cr.set_source_rgb(r, g, b);
+                self.draw_text(cr, (x + 0.5 * step, y), &format!("+{}", advantage))?;
             }
-            None => (),
         }
 
+        cr.restore()?;
+        Ok(())
+    }
+
+    fn draw_inline_coordinates(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if !self.show_coordinates || self.coordinates_style != CoordinatesStyle::Inline {
+            return Ok(());
+        }
+
+        // offsets of the label centers within a square, given in the frame
+        // of the viewer and mapped back to board coordinates
+        let (near, far) = (0.12, 0.88);
+        let corner = |(dx, dy): (f64, f64)| self.orientation.fold_wb((dx, dy), (1.0 - dx, 1.0 - dy));
+        let bottom = self.orientation.fold_wb(Rank::First, Rank::Eighth);
+        let left = self.orientation.fold_wb(File::A, File::H);
+
+        cr.set_font_size(0.18);
+
+        for (file, glyph) in ["a", "b", "c", "d", "e", "f", "g", "h"].iter().enumerate() {
+            let square = Square::from_coords(File::new(file as u32), bottom);
+            let (dx, dy) = corner((far, far));
+            self.set_inline_coordinate_color(cr, square);
+            self.draw_text(cr, (file_to_float(square.file()) + dx, 7.0 - rank_to_float(square.rank()) + dy), glyph)?;
+        }
+
+        for (rank, glyph) in ["1", "2", "3", "4", "5", "6", "7", "8"].iter().enumerate() {
+            let square = Square::from_coords(left, Rank::new(rank as u32));
+            let (dx, dy) = corner((near, near));
+            self.set_inline_coordinate_color(cr, square);
+            self.draw_text(cr, (file_to_float(square.file()) + dx, 7.0 - rank_to_float(square.rank()) + dy), glyph)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_inline_coordinate_color(&self, cr: &Context, square: Square) {
+        // use the color of the opposite squares
+        let (text, background) = if square.is_light() {
+            (self.theme.dark, self.theme.light)
+        } else {
+            (self.theme.light, self.theme.dark)
+        };
+        let (r, g, b) = self.readable(text, background);
+        cr.set_source_rgb(r, g, b);
+    }
+
+    /// Replace the text color with black or white, whichever stands out
+    /// more, if it does not contrast enough with the background.
+    fn readable(&self, text: (f64, f64, f64), background: (f64, f64, f64)) -> (f64, f64, f64) {
+        match self.coordinates_min_contrast {
+            Some(min_contrast) if contrast(text, background) < min_contrast => {
+                let (black, white) = ((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+                if contrast(black, background) >= contrast(white, background) { black } else { white }
+            }
+            _ => text,
+        }
+    }
+
+    fn draw_turn(&self, cr: &Context) -> Result<(), cairo::Error> {
+        // position and shade of the dot in the corner of each side
+        let dot = |color: Color| color.fold_wb((8.25, 1.0), (-0.25, 0.0));
+
+        // slide the dot to the other corner or fade it in or out
+        let t = if self.turn_animation { self.turn_elapsed } else { 1.0 };
+        let (y, shade, alpha) = match (self.previous_turn, self.turn) {
+            (Some(previous), Some(turn)) => {
+                let ((y1, shade1), (y2, shade2)) = (dot(previous), dot(turn));
+                (ease(y1, y2, t), ease(shade1, shade2, t), 1.0)
+            }
+            (None, Some(turn)) => {
+                let (y, shade) = dot(turn);
+                (y, shade, ease(0.0, 1.0, t))
+            }
+            (Some(previous), None) => {
+                let (y, shade) = dot(previous);
+                (y, shade, ease(1.0, 0.0, t))
+            }
+            (None, None) => return Ok(()),
+        };
+
+        cr.set_source_rgba(shade, shade, shade, alpha);
+        cr.arc(8.25, y, 0.1, 0.0, 2.0 * PI);
+        cr.fill()?;
+
         Ok(())
     }
 
@@ -161,7 +765,7 @@ impl BoardState {
 
         cr.save()?;
         cr.translate(x, y);
-        cr.rotate(self.orientation.fold_wb(0.0, PI));
+        cr.rotate(self.upright());
         cr.move_to(-0.5 * e.width, 0.5 * font.height - font.descent);
         cr.show_text(text)?;
         cr.restore()?;
@@ -171,10 +775,12 @@ impl BoardState {
 
     fn draw_board(&self, cr: &Context) -> Result<(), cairo::Error> {
         cr.rectangle(0.0, 0.0, 8.0, 8.0);
-        cr.set_source_rgb(0.55, 0.64, 0.68); // dark
+        let (r, g, b) = self.theme.dark;
+        cr.set_source_rgb(r, g, b);
         cr.fill()?;
 
-        cr.set_source_rgb(0.87, 0.89, 0.90); // light
+        let (r, g, b) = self.theme.light;
+        cr.set_source_rgb(r, g, b);
 
         for square in Square::ALL {
             if square.is_light() {
@@ -183,17 +789,53 @@ impl BoardState {
             }
         }
 
+        if let Some((r, g, b, a)) = self.theme.grid_lines {
+            cr.set_source_rgba(r, g, b, a);
+            cr.set_line_width(0.02);
+            for i in 1..8 {
+                let i = f64::from(i);
+                cr.move_to(i, 0.0);
+                cr.line_to(i, 8.0);
+                cr.move_to(0.0, i);
+                cr.line_to(8.0, i);
+            }
+            cr.stroke()?;
+        }
+
         Ok(())
     }
 
     fn draw_last_move(&self, cr: &Context) -> Result<(), cairo::Error> {
         if let Some((orig, dest)) = self.last_move {
-            cr.set_source_rgba(0.61, 0.78, 0.0, 0.41);
-            cr.rectangle(file_to_float(orig.file()), 7.0 - rank_to_float(orig.rank()), 1.0, 1.0);
-            cr.fill()?;
+            let (r, g, b, a) = self.theme.last_move;
+            let a = if self.last_move_fade.is_some() {
+                ease(a, 0.0, self.last_move_elapsed)
+            } else {
+                a
+            };
+            let (x, y) = (file_to_float(dest.file()), 7.0 - rank_to_float(dest.rank()));
+
+            if dest == orig {
+                // a piece was dropped: a lighter tint with a plus sign in
+                // the corner
+                cr.set_source_rgba(r, g, b, 0.5 * a);
+                cr.rectangle(x, y, 1.0, 1.0);
+                cr.fill()?;
 
-            if dest != orig {
-                cr.rectangle(file_to_float(dest.file()), 7.0 - rank_to_float(dest.rank()), 1.0, 1.0);
+                cr.set_source_rgba(r, g, b, a);
+                cr.set_line_width(0.06);
+                cr.move_to(x + 0.08, y + 0.2);
+                cr.rel_line_to(0.24, 0.0);
+                cr.move_to(x + 0.2, y + 0.08);
+                cr.rel_line_to(0.0, 0.24);
+                cr.stroke()?;
+            } else {
+                cr.set_source_rgba(r, g, b, a);
+                cr.rectangle(file_to_float(orig.file()), 7.0 - rank_to_float(orig.rank()), 1.0, 1.0);
+                cr.rectangle(x, y, 1.0, 1.0);
+                for square in self.last_move_extra {
+                    cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+                }
                 cr.fill()?;
             }
         }
@@ -201,14 +843,31 @@ impl BoardState {
         Ok(())
     }
 
+    fn draw_highlights(&self, cr: &Context) -> Result<(), cairo::Error> {
+        for (square, &(r, g, b, a)) in &self.highlights {
+            cr.set_source_rgba(r, g, b, a);
+            cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
     fn draw_check(&self, cr: &Context) -> Result<(), cairo::Error> {
+        let (r, g, b) = self.theme.check;
+        for square in self.checkers {
+            cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+        }
+        cr.set_source_rgba(r, g, b, 0.35);
+        cr.fill()?;
+
         if let Some(check) = self.check {
             let cx = 0.5 + file_to_float(check.file());
             let cy = 7.5 - rank_to_float(check.rank());
             let gradient = RadialGradient::new(cx, cy, 0.0, cx, cy, 0.5f64.hypot(0.5));
-            gradient.add_color_stop_rgba(0.0, 1.0, 0.0, 0.0, 1.0);
-            gradient.add_color_stop_rgba(0.25, 0.91, 0.0, 0.0, 1.0);
-            gradient.add_color_stop_rgba(0.89, 0.66, 0.0, 0.0, 0.0);
+            gradient.add_color_stop_rgba(0.0, r, g, b, 1.0);
+            gradient.add_color_stop_rgba(0.25, 0.91 * r, 0.91 * g, 0.91 * b, 1.0);
+            gradient.add_color_stop_rgba(0.89, 0.66 * r, 0.66 * g, 0.66 * b, 0.0);
             cr.set_source(&gradient)?;
             cr.paint()?;
         }
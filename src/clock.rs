@@ -0,0 +1,72 @@
+// This file is part of the chessground library.
+// Copyright (C) 2017 Niklas Fiekas <niklas.fiekas@backscattering.de>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+
+use time::{Duration, SteadyTime};
+
+/// Source of time for animations.
+///
+/// The widget uses the `SystemClock` by default. A `ManualClock` can be
+/// injected with `GroundMsg::SetClock` to step through animations
+/// deterministically.
+pub trait Clock: Debug {
+    /// The current time.
+    fn now(&self) -> SteadyTime;
+}
+
+/// The monotonic system clock.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SteadyTime {
+        SteadyTime::now()
+    }
+}
+
+/// A clock that only moves forward when advanced explicitly.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Cell<SteadyTime>,
+}
+
+impl ManualClock {
+    /// Creates a clock stopped at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Cell::new(SteadyTime::now()),
+        }
+    }
+
+    /// Moves the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SteadyTime {
+        self.now.get()
+    }
+}
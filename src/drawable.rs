@@ -14,14 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::error::Error;
 use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
 
 use gdk::{EventButton, ModifierType};
-use cairo::Context;
+use cairo::{Context, LinearGradient, LineJoin};
 
-use shakmaty::Square;
+use shakmaty::{attacks, Bitboard, Piece, Square};
 
 use ground::{EventContext, GroundMsg};
+use boardstate::BoardState;
 
 use util::{file_to_float, rank_to_float};
 
@@ -32,21 +36,94 @@ pub enum DrawBrush {
     Red,
     Blue,
     Yellow,
+    /// Any color (RGBA), for example to shade arrows by engine score.
+    /// Drawn with its own alpha rather than the usual opacity. Written
+    /// as `#rrggbbaa` in shape lists.
+    Custom(u8, u8, u8, u8),
+}
+
+impl DrawBrush {
+    fn from_name(name: &str) -> Option<DrawBrush> {
+        match name {
+            "green" => Some(DrawBrush::Green),
+            "red" => Some(DrawBrush::Red),
+            "blue" => Some(DrawBrush::Blue),
+            "yellow" => Some(DrawBrush::Yellow),
+            _ if name.len() == 9 && name.starts_with('#') => {
+                let channel = |i: usize| u8::from_str_radix(name.get(i..i + 2)?, 16).ok();
+                Some(DrawBrush::Custom(channel(1)?, channel(3)?, channel(5)?, channel(7)?))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn rgba(self, opacity: f64) -> (f64, f64, f64, f64) {
+        match self {
+            DrawBrush::Green => (0.08, 0.47, 0.11, opacity),
+            DrawBrush::Red => (0.53, 0.13, 0.13, opacity),
+            DrawBrush::Blue => (0.0, 0.19, 0.53, opacity),
+            DrawBrush::Yellow => (0.90, 0.94, 0.0, opacity),
+            DrawBrush::Custom(r, g, b, a) => {
+                let channel = |c: u8| f64::from(c) / 255.0;
+                (channel(r), channel(g), channel(b), channel(a))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DrawBrush {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DrawBrush::Green => f.write_str("green"),
+            DrawBrush::Red => f.write_str("red"),
+            DrawBrush::Blue => f.write_str("blue"),
+            DrawBrush::Yellow => f.write_str("yellow"),
+            DrawBrush::Custom(r, g, b, a) => write!(f, "#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+        }
+    }
+}
+
+/// What happens when the user draws a shape that already exists.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ShapeToggle {
+    /// Remove the existing shape.
+    Toggle,
+    /// Add the shape again.
+    AlwaysAdd,
+    /// Keep the existing shape, unless Ctrl is held, which removes it.
+    ModifierRemove,
 }
 
 /// An arrow or circle drawn on the board.
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct DrawShape {
     orig: Square,
     dest: Square,
     brush: DrawBrush,
+    label: Option<String>,
+    // in percent, so that shapes can still be compared and hashed
+    opacity: u8,
 }
 
+#[derive(Clone)]
 pub struct Drawable {
     drawing: Option<DrawShape>,
     shapes: Vec<DrawShape>,
+    sticky_shapes: Vec<DrawShape>,
+    best_move: Option<DrawShape>,
+    // the ponder move, the preview and the line have their own styles and
+    // ignore the brushes of their shapes
+    ponder_move: Option<DrawShape>,
+    preview: Option<(DrawShape, Piece)>,
+    line: Vec<DrawShape>,
+    path: Vec<Square>,
+    dots: Vec<(Square, (f64, f64, f64, f64))>,
+    diff: Option<(Bitboard, Bitboard, Bitboard)>,
     enabled: bool,
     erase_on_click: bool,
+    curved_arrows: bool,
+    allowed_brushes: Vec<DrawBrush>,
+    toggle: ShapeToggle,
 }
 
 impl Drawable {
@@ -54,11 +131,110 @@ impl Drawable {
         Drawable {
             drawing: None,
             shapes: Vec::new(),
+            sticky_shapes: Vec::new(),
+            best_move: None,
+            ponder_move: None,
+            preview: None,
+            line: Vec::new(),
+            path: Vec::new(),
+            dots: Vec::new(),
+            diff: None,
             enabled: true,
             erase_on_click: true,
+            curved_arrows: false,
+            allowed_brushes: vec![DrawBrush::Green, DrawBrush::Red, DrawBrush::Blue, DrawBrush::Yellow],
+            toggle: ShapeToggle::Toggle,
         }
     }
 
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.drawing = None;
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_erase_on_click(&mut self, erase_on_click: bool) {
+        self.erase_on_click = erase_on_click;
+    }
+
+    pub fn erase_on_click(&self) -> bool {
+        self.erase_on_click
+    }
+
+    pub fn set_curved_arrows(&mut self, curved_arrows: bool) {
+        self.curved_arrows = curved_arrows;
+    }
+
+    pub fn set_toggle(&mut self, toggle: ShapeToggle) {
+        self.toggle = toggle;
+    }
+
+    pub fn toggle(&self) -> ShapeToggle {
+        self.toggle
+    }
+
+    pub fn set_shapes(&mut self, shapes: Vec<DrawShape>) {
+        self.shapes = shapes;
+    }
+
+    /// Drop the shape being drawn, returning whether there was one.
+    pub fn cancel_drawing(&mut self) -> bool {
+        self.drawing.take().is_some()
+    }
+
+    pub fn clear_shapes(&mut self) {
+        self.shapes.clear();
+        self.drawing = None;
+    }
+
+    pub fn set_sticky_shapes(&mut self, shapes: Vec<DrawShape>) {
+        self.sticky_shapes = shapes;
+    }
+
+    pub fn set_allowed_brushes(&mut self, brushes: Vec<DrawBrush>) {
+        self.allowed_brushes = brushes;
+    }
+
+    fn curved(&self, shape: &DrawShape, occupied: Bitboard) -> bool {
+        // only bend arrows that would cover pieces on their way
+        self.curved_arrows && shape.is_arrow() && (attacks::between(shape.orig, shape.dest) & occupied).any()
+    }
+
+    pub fn set_best_move(&mut self, best_move: Option<(Square, Square)>) {
+        self.best_move = best_move.map(|(orig, dest)| DrawShape::new(orig, dest, DrawBrush::Blue));
+    }
+
+    pub fn set_ponder_move(&mut self, ponder_move: Option<(Square, Square)>) {
+        self.ponder_move = ponder_move.map(|(orig, dest)| DrawShape::new(orig, dest, DrawBrush::Blue));
+    }
+
+    pub fn set_preview(&mut self, preview: Option<(Square, Square, Piece)>) {
+        self.preview = preview.map(|(orig, dest, piece)| (DrawShape::new(orig, dest, DrawBrush::Green), piece));
+    }
+
+    pub fn set_line(&mut self, line: Vec<(Square, Square)>) {
+        self.line = line.into_iter().map(|(orig, dest)| DrawShape::new(orig, dest, DrawBrush::Blue)).collect();
+    }
+
+    pub fn set_path(&mut self, path: Vec<Square>) {
+        self.path = path;
+    }
+
+    pub fn set_dots(&mut self, dots: Vec<(Square, (f64, f64, f64, f64))>) {
+        self.dots = dots;
+    }
+
+    /// Tint squares where pieces were added, removed or changed, or
+    /// clear the tints with `None`.
+    pub fn set_diff(&mut self, diff: Option<(Bitboard, Bitboard, Bitboard)>) {
+        self.diff = diff;
+    }
+
     pub(crate) fn mouse_down(&mut self, ctx: &EventContext, e: &EventButton) {
         if !self.enabled {
             return;
@@ -73,7 +249,8 @@ impl Drawable {
                 }
             }
             3 => {
-                self.drawing = ctx.square().map(|square| {
+                let allowed = &self.allowed_brushes;
+                self.drawing = ctx.square().and_then(|square| {
                     let brush = if e.state().contains(ModifierType::MOD1_MASK | ModifierType::SHIFT_MASK) {
                         DrawBrush::Yellow
                     } else if e.state().contains(ModifierType::MOD1_MASK) {
@@ -84,13 +261,20 @@ impl Drawable {
                         DrawBrush::Green
                     };
 
-                    DrawShape {
-                        orig: square,
-                        dest: square,
-                        brush,
-                    }
+                    // fall back to the first allowed brush
+                    let brush = if allowed.contains(&brush) {
+                        brush
+                    } else {
+                        *allowed.first()?
+                    };
+
+                    Some(DrawShape::new(square, square, brush))
                 });
 
+                if self.drawing.is_some() {
+                    ctx.stream().emit(GroundMsg::ShapeDrawing(self.drawing.clone()));
+                }
+
                 ctx.widget().queue_draw();
             }
             _ => {}
@@ -101,21 +285,31 @@ impl Drawable {
         if let Some(ref mut drawing) = self.drawing {
             let dest = ctx.square().unwrap_or(drawing.orig);
             if drawing.dest != dest {
+                drawing.dest = dest;
+                ctx.stream().emit(GroundMsg::ShapeDrawing(Some(drawing.clone())));
                 ctx.widget().queue_draw();
             }
-            drawing.dest = dest;
         }
     }
 
-    pub(crate) fn mouse_up(&mut self, ctx: &EventContext) {
+    pub(crate) fn mouse_up(&mut self, ctx: &EventContext, e: &EventButton) {
         if let Some(mut drawing) = self.drawing.take() {
+            ctx.stream().emit(GroundMsg::ShapeDrawing(None));
+
             if self.enabled {
                 drawing.dest = ctx.square().unwrap_or(drawing.orig);
 
                 // remove or add shape
-                let num_shapes = self.shapes.len();
-                self.shapes.retain(|s| s.orig != drawing.orig || s.dest != drawing.dest);
-                if num_shapes == self.shapes.len() {
+                let remove = match self.toggle {
+                    ShapeToggle::Toggle => true,
+                    ShapeToggle::AlwaysAdd => false,
+                    ShapeToggle::ModifierRemove => e.state().contains(ModifierType::CONTROL_MASK),
+                };
+
+                let existing = self.shapes.iter().any(|s| s.orig == drawing.orig && s.dest == drawing.dest);
+                if existing && remove {
+                    self.shapes.retain(|s| s.orig != drawing.orig || s.dest != drawing.dest);
+                } else if !existing || self.toggle == ShapeToggle::AlwaysAdd {
                     self.shapes.push(drawing);
                 }
 
@@ -126,20 +320,171 @@ impl Drawable {
         }
     }
 
-    pub(crate) fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
-        for shape in &self.shapes {
-            shape.draw(cr)?;
+    pub(crate) fn draw_tints(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some(ref best_move) = self.best_move {
+            cr.set_source_rgba(0.0, 0.19, 0.53, 0.15);
+            for square in &[best_move.orig, best_move.dest] {
+                cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+                cr.fill()?;
+            }
+        }
+
+        if let Some((added, removed, changed)) = self.diff {
+            let tints = [
+                (added, (0.2, 0.6, 0.2)),
+                (removed, (0.7, 0.15, 0.15)),
+                (changed, (0.85, 0.6, 0.0)),
+            ];
+
+            for &(squares, (r, g, b)) in &tints {
+                cr.set_source_rgba(r, g, b, 0.35);
+                for square in squares {
+                    cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+                }
+                cr.fill()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn draw(&self, cr: &Context, state: &BoardState, occupied: Bitboard) -> Result<(), cairo::Error> {
+        let upright = state.upright();
+
+        if let Some(ref best_move) = self.best_move {
+            best_move.draw(cr, self.curved(best_move, occupied), upright)?;
+        }
+
+        if let Some(ref ponder_move) = self.ponder_move {
+            cr.save()?;
+            cr.set_source_rgba(0.4, 0.4, 0.4, 0.5);
+            cr.set_dash(&[0.25, 0.15], 0.0);
+            ponder_move.draw_path(cr, self.curved(ponder_move, occupied))?;
+            cr.restore()?;
+        }
+
+        for &(square, (r, g, b, a)) in &self.dots {
+            // the size of the dots of move hints
+            let (x, y) = DrawShape::center(square);
+            cr.set_source_rgba(r, g, b, a);
+            cr.arc(x, y, 0.12, 0.0, 2.0 * PI);
+            cr.fill()?;
+        }
+
+        self.draw_line(cr, occupied)?;
+        self.draw_path(cr, upright)?;
+
+        for shape in self.sticky_shapes.iter().chain(&self.shapes) {
+            shape.draw(cr, self.curved(shape, occupied), upright)?;
+        }
+
+        if let Some((ref preview, ref piece)) = self.preview {
+            cr.save()?;
+            cr.set_source_rgba(0.2, 0.2, 0.2, 0.4);
+            preview.draw_path(cr, self.curved(preview, occupied))?;
+            cr.restore()?;
+
+            // small glyph of the moving piece in the corner of the destination
+            let (x, y) = DrawShape::center(preview.dest);
+            cr.save()?;
+            cr.translate(x, y);
+            cr.rotate(upright);
+            cr.translate(0.05, 0.05);
+            cr.scale(0.4, 0.4);
+            cr.push_group();
+            state.draw_piece(cr, piece)?;
+            cr.pop_group_to_source()?;
+            cr.paint_with_alpha(0.8)?;
+            cr.restore()?;
         }
 
         if let Some(ref shape) = self.drawing {
-            shape.draw(cr)?;
+            shape.draw(cr, self.curved(shape, occupied), upright)?;
         }
 
         Ok(())
     }
 }
 
+impl Drawable {
+    fn draw_line(&self, cr: &Context, occupied: Bitboard) -> Result<(), cairo::Error> {
+        // from dark for the first move to light for the last move, with
+        // each arrow blending into the next along its length
+        let shade = |depth: f64| (0.6 * depth, 0.19 + 0.56 * depth, 0.53 + 0.47 * depth);
+
+        let depth = self.line.len() as f64;
+        for (i, shape) in self.line.iter().enumerate() {
+            let ((orig_x, orig_y), (dest_x, dest_y)) = (DrawShape::center(shape.orig), DrawShape::center(shape.dest));
+            let gradient = LinearGradient::new(orig_x, orig_y, dest_x, dest_y);
+            for &(offset, ply) in &[(0.0, i), (1.0, i + 1)] {
+                let (r, g, b) = shade(ply as f64 / depth);
+                gradient.add_color_stop_rgba(offset, r, g, b, 0.6);
+            }
+
+            cr.set_source(&gradient)?;
+            shape.draw_path(cr, self.curved(shape, occupied))?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_path(&self, cr: &Context, upright: f64) -> Result<(), cairo::Error> {
+        if self.path.is_empty() {
+            return Ok(());
+        }
+
+        cr.save()?;
+        cr.set_source_rgba(0.15, 0.15, 0.25, 0.7);
+
+        // connect the centers of the squares
+        cr.set_line_width(0.08);
+        cr.set_line_join(LineJoin::Round);
+        for &square in &self.path {
+            let (x, y) = DrawShape::center(square);
+            cr.line_to(x, y);
+        }
+        cr.stroke()?;
+
+        // numbered nodes, starting with 1
+        cr.set_font_size(0.2);
+        let font = cr.font_extents()?;
+        for (i, &square) in self.path.iter().enumerate() {
+            let (x, y) = DrawShape::center(square);
+            let number = (i + 1).to_string();
+
+            cr.arc(x, y, 0.17, 0.0, 2.0 * PI);
+            cr.fill()?;
+
+            let e = cr.text_extents(&number)?;
+            cr.save()?;
+            cr.translate(x, y);
+            cr.rotate(upright);
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+            cr.move_to(-0.5 * e.width - e.x_bearing, 0.5 * font.height - font.descent);
+            cr.show_text(&number)?;
+            cr.restore()?;
+        }
+
+        cr.restore()?;
+        Ok(())
+    }
+}
+
 impl DrawShape {
+    /// Create an arrow from `orig` to `dest`, or a circle if both squares
+    /// are the same.
+    pub fn new(orig: Square, dest: Square, brush: DrawBrush) -> DrawShape {
+        DrawShape { orig, dest, brush, label: None, opacity: 50 }
+    }
+
+    /// Set the opacity of the shape, for example to show secondary
+    /// arrows faintly. Defaults to 0.5 and is kept to one percent. Custom
+    /// brushes have their own alpha instead.
+    pub fn with_opacity(mut self, opacity: f64) -> DrawShape {
+        self.opacity = (opacity.max(0.0).min(1.0) * 100.0).round() as u8;
+        self
+    }
+
     /// First square.
     pub fn orig(&self) -> Square {
         self.orig
@@ -155,6 +500,16 @@ impl DrawShape {
         self.brush
     }
 
+    /// Opacity of the shape.
+    pub fn opacity(&self) -> f64 {
+        f64::from(self.opacity) / 100.0
+    }
+
+    /// Short text shown in the middle of the shape.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
     /// Check if the shape is a circle.
     pub fn is_circle(&self) -> bool {
         self.orig == self.dest
@@ -165,20 +520,80 @@ impl DrawShape {
         self.orig != self.dest
     }
 
-    fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
-        let opacity = 0.5;
+    /// Parse a comma separated list of shapes, like
+    /// `e2e4 green, d7d5 red, e4 blue`.
+    ///
+    /// Arrows are given as a pair of squares, circles as a single square,
+    /// each optionally followed by a brush name (defaults to `green`) and
+    /// a label without commas, like `e2e4 green main line`.
+    pub fn parse_list(s: &str) -> Result<Vec<DrawShape>, ParseDrawShapeError> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|shape| !shape.is_empty())
+            .map(str::parse)
+            .collect()
+    }
 
-        match self.brush {
-            DrawBrush::Green => cr.set_source_rgba(0.08, 0.47, 0.11, opacity),
-            DrawBrush::Red => cr.set_source_rgba(0.53, 0.13, 0.13, opacity),
-            DrawBrush::Blue => cr.set_source_rgba(0.0, 0.19, 0.53, opacity),
-            DrawBrush::Yellow => cr.set_source_rgba(0.90, 0.94, 0.0, opacity),
-        }
+    fn draw(&self, cr: &Context, curved: bool, upright: f64) -> Result<(), cairo::Error> {
+        let (r, g, b, a) = self.brush.rgba(self.opacity());
+        cr.set_source_rgba(r, g, b, a);
+
+        self.draw_path(cr, curved)?;
+        self.draw_label(cr, curved, upright)
+    }
+
+    fn center(square: Square) -> (f64, f64) {
+        (0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank()))
+    }
+
+    /// Control point of a curved arrow. Curved arrows always bow to the
+    /// same side, with the control point of a quadratic curve next to the
+    /// middle of the arrow.
+    fn control(&self) -> (f64, f64) {
+        let ((orig_x, orig_y), (dest_x, dest_y)) = (DrawShape::center(self.orig), DrawShape::center(self.dest));
+        let (dx, dy) = (dest_x - orig_x, dest_y - orig_y);
+        let bend = 0.4 / dx.hypot(dy);
+        (0.5 * (orig_x + dest_x) - dy * bend, 0.5 * (orig_y + dest_y) + dx * bend)
+    }
+
+    fn draw_label(&self, cr: &Context, curved: bool, upright: f64) -> Result<(), cairo::Error> {
+        let label = match self.label {
+            Some(ref label) => label,
+            None => return Ok(()),
+        };
+
+        let ((orig_x, orig_y), (dest_x, dest_y)) = (DrawShape::center(self.orig), DrawShape::center(self.dest));
+        let (x, y) = if curved && self.is_arrow() {
+            let (cx, cy) = self.control();
+            (0.25 * orig_x + 0.5 * cx + 0.25 * dest_x, 0.25 * orig_y + 0.5 * cy + 0.25 * dest_y)
+        } else {
+            (0.5 * (orig_x + dest_x), 0.5 * (orig_y + dest_y))
+        };
+
+        cr.save()?;
+        cr.set_font_size(0.25);
+        let font = cr.font_extents()?;
+        let e = cr.text_extents(label)?;
 
-        let orig_x = 0.5 + file_to_float(self.orig.file());
-        let orig_y = 7.5 - rank_to_float(self.orig.rank());
-        let dest_x = 0.5 + file_to_float(self.dest.file());
-        let dest_y = 7.5 - rank_to_float(self.dest.rank());
+        cr.translate(x, y);
+        cr.rotate(upright);
+
+        // backdrop in the color of the brush
+        let padding = 0.05;
+        cr.rectangle(-0.5 * e.width - padding, -0.5 * font.height - padding,
+                     e.width + 2.0 * padding, font.height + 2.0 * padding);
+        cr.fill()?;
+
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        cr.move_to(-0.5 * e.width - e.x_bearing, 0.5 * font.height - font.descent);
+        cr.show_text(label)?;
+        cr.restore()?;
+
+        Ok(())
+    }
+
+    fn draw_path(&self, cr: &Context, curved: bool) -> Result<(), cairo::Error> {
+        let ((orig_x, orig_y), (dest_x, dest_y)) = (DrawShape::center(self.orig), DrawShape::center(self.dest));
 
         if self.is_circle() {
             // draw circle
@@ -191,7 +606,10 @@ impl DrawShape {
             let marker_size = 0.75;
             let margin = 0.1;
 
-            let (dx, dy) = (dest_x - orig_x, dest_y - orig_y);
+            let control = if curved { Some(self.control()) } else { None };
+
+            // the head points along the tangent at the destination
+            let (dx, dy) = control.map_or((dest_x - orig_x, dest_y - orig_y), |(cx, cy)| (dest_x - cx, dest_y - cy));
             let hypot = dx.hypot(dy);
 
             let shaft_x = dest_x - dx * (marker_size + margin) / hypot;
@@ -205,7 +623,15 @@ impl DrawShape {
 
             // shaft
             cr.move_to(orig_x, orig_y);
-            cr.line_to(shaft_x, shaft_y);
+            match control {
+                Some((cx, cy)) => {
+                    // quadratic curve as a cubic one
+                    cr.curve_to(orig_x + 2.0 / 3.0 * (cx - orig_x), orig_y + 2.0 / 3.0 * (cy - orig_y),
+                                shaft_x + 2.0 / 3.0 * (cx - shaft_x), shaft_y + 2.0 / 3.0 * (cy - shaft_y),
+                                shaft_x, shaft_y);
+                }
+                None => cr.line_to(shaft_x, shaft_y),
+            }
             cr.stroke()?;
 
             // arrow head
@@ -220,3 +646,152 @@ impl DrawShape {
         Ok(())
     }
 }
+
+impl FromStr for DrawShape {
+    type Err = ParseDrawShapeError;
+
+    fn from_str(s: &str) -> Result<DrawShape, ParseDrawShapeError> {
+        let err = || ParseDrawShapeError { shape: s.to_owned() };
+
+        let (squares, rest) = split_word(s);
+        let (brush, label) = split_word(rest);
+
+        let (orig, dest) = match squares.len() {
+            2 => {
+                let square = squares.parse::<Square>().map_err(|_| err())?;
+                (square, square)
+            }
+            4 => {
+                let orig = squares.get(..2).ok_or_else(err)?.parse::<Square>().map_err(|_| err())?;
+                let dest = squares.get(2..).ok_or_else(err)?.parse::<Square>().map_err(|_| err())?;
+                (orig, dest)
+            }
+            _ => return Err(err()),
+        };
+
+        let brush = match brush {
+            "" => DrawBrush::Green,
+            name => DrawBrush::from_name(name).ok_or_else(err)?,
+        };
+
+        let label = if label.is_empty() { None } else { Some(label.to_owned()) };
+
+        Ok(DrawShape { orig, dest, brush, label, opacity: 50 })
+    }
+}
+
+impl fmt::Display for DrawShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_circle() {
+            write!(f, "{} {}", self.orig, self.brush)?;
+        } else {
+            write!(f, "{}{} {}", self.orig, self.dest, self.brush)?;
+        }
+
+        if let Some(ref label) = self.label {
+            write!(f, " {}", label)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Split off the first word and trim the rest.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Error when parsing an invalid shape.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseDrawShapeError {
+    shape: String,
+}
+
+impl fmt::Display for ParseDrawShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid shape: {:?}", self.shape)
+    }
+}
+
+impl Error for ParseDrawShapeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let shapes = DrawShape::parse_list("e2e4 green, d7d5 red, e4 blue").expect("valid shapes");
+        assert_eq!(shapes, vec![
+            DrawShape::new(Square::E2, Square::E4, DrawBrush::Green),
+            DrawShape::new(Square::D7, Square::D5, DrawBrush::Red),
+            DrawShape::new(Square::E4, Square::E4, DrawBrush::Blue),
+        ]);
+        assert!(shapes[2].is_circle());
+    }
+
+    #[test]
+    fn test_parse_defaults_and_labels() {
+        let shape: DrawShape = "g1f3".parse().expect("valid shape");
+        assert_eq!(shape, DrawShape::new(Square::G1, Square::F3, DrawBrush::Green));
+
+        let shape: DrawShape = "e2e4 yellow main line".parse().expect("valid shape");
+        assert_eq!(shape.brush(), DrawBrush::Yellow);
+        assert_eq!(shape.label(), Some("main line"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let s = "e2e4 green main line, d7d5 red, e4 blue, a1h8 yellow 1";
+        let shapes = DrawShape::parse_list(s).expect("valid shapes");
+        let formatted = shapes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        assert_eq!(formatted, s);
+        assert_eq!(DrawShape::parse_list(&formatted), Ok(shapes));
+    }
+
+    #[test]
+    fn test_separators() {
+        assert_eq!(DrawShape::parse_list(""), Ok(Vec::new()));
+        assert_eq!(DrawShape::parse_list(" , ,"), Ok(Vec::new()));
+        assert_eq!(DrawShape::parse_list("e2e4 green,").map(|shapes| shapes.len()), Ok(1));
+        assert_eq!(DrawShape::parse_list("e2e4,, d7d5 , ").map(|shapes| shapes.len()), Ok(2));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!("".parse::<DrawShape>().is_err());
+        assert!("e9".parse::<DrawShape>().is_err());
+        assert!("e2e9 green".parse::<DrawShape>().is_err());
+        assert!("i2e4".parse::<DrawShape>().is_err());
+        assert!("e2e".parse::<DrawShape>().is_err());
+        assert!("e2e4e6".parse::<DrawShape>().is_err());
+        assert!("e2e4 purple".parse::<DrawShape>().is_err());
+        assert!("e2e4 Green".parse::<DrawShape>().is_err());
+
+        assert!(DrawShape::parse_list("e2e4 green, e9 red").is_err());
+        assert!(DrawShape::parse_list("e2e4 green, d7d5 purple,").is_err());
+    }
+
+    #[test]
+    fn test_custom_brush() {
+        let shape: DrawShape = "e2e4 #ff800040".parse().expect("valid shape");
+        assert_eq!(shape.brush(), DrawBrush::Custom(0xff, 0x80, 0x00, 0x40));
+        assert_eq!(shape.to_string(), "e2e4 #ff800040");
+
+        assert!("e2e4 #ff8000".parse::<DrawShape>().is_err());
+        assert!("e2e4 #ff8000zz".parse::<DrawShape>().is_err());
+    }
+
+    #[test]
+    fn test_opacity() {
+        let shape = DrawShape::new(Square::E2, Square::E4, DrawBrush::Green);
+        assert_eq!(shape.opacity(), 0.5);
+        assert_eq!(shape.clone().with_opacity(0.3).opacity(), 0.3);
+        assert_eq!(shape.clone().with_opacity(0.501), shape);
+        assert_ne!(shape.clone().with_opacity(0.2), shape);
+    }
+}
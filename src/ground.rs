@@ -16,29 +16,54 @@
 
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
-use std::f64::consts::PI;
 use std::cmp::{min, max};
+use std::error::Error;
 use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::Path;
 
 use gtk::prelude::*;
 use gtk::DrawingArea;
-use gdk::{EventButton, EventMotion, EventMask};
-use cairo::{Context, Matrix};
+use gdk::{EventButton, EventMotion, EventMask, EventScroll, EventType, ScrollDirection};
+use gdk::gdk_pixbuf::Pixbuf;
+use cairo::{Context, Format, ImageSurface, Matrix, PdfSurface, SvgSurface};
+
+use time::{Duration, SteadyTime};
 
 use relm::{Relm, Widget, Update, StreamHandle};
 
-use shakmaty::{Square, Rank, Color, Role, Board, Move, MoveList, Chess, Position};
+use shakmaty::{Square, Rank, Color, Role, Piece, Bitboard, Board, Move, MoveList, Chess, Position, File};
 
-use util::{file_to_float, pos_to_square, rank_to_float};
-use pieces::Pieces;
-use drawable::{Drawable, DrawShape};
+use util::{file_to_float, pos_to_square, rank_to_float, square_to_pos};
+use pieces::{Pieces, FigurineState, SelectionStyle, MoveHintStyle, DragGhostStyle};
+use drawable::{Drawable, DrawBrush, DrawShape, ShapeToggle};
 use promotable::Promotable;
-use boardstate::BoardState;
+use boardstate::{BoardState, BoardTheme, CoordinatesStyle, FlipAnimation, MaterialBalance};
+use clock::{Clock, SystemClock};
+use pieceset::PieceSet;
+
+/// Handle to send messages to the board and its parent, which first shows
+/// them to the message tap, if any.
+#[derive(Clone)]
+pub(crate) struct Stream {
+    handle: StreamHandle<GroundMsg>,
+    tap: Rc<RefCell<Option<Box<dyn Fn(&GroundMsg)>>>>,
+}
 
-type Stream = StreamHandle<GroundMsg>;
+impl Stream {
+    pub fn emit(&self, msg: GroundMsg) {
+        if let Some(ref tap) = *self.tap.borrow() {
+            tap(&msg);
+        }
+        self.handle.emit(msg);
+    }
+}
 
 pub struct Model {
     state: Rc<RefCell<State>>,
+    stream: Stream,
+    batching: bool,
 }
 
 impl fmt::Debug for Model {
@@ -54,15 +79,285 @@ pub enum GroundMsg {
     Flip,
     /// Set the board orientation.
     SetOrientation(Color),
+    /// Ask for the current board orientation, which is sent back with
+    /// `OrientationChanged`.
+    RequestOrientation,
+    /// Apply several interaction options at once, for example to switch
+    /// between analysis and puzzle profiles. See `Ground::interaction()`
+    /// for the current options.
+    SetInteraction(InteractionConfig),
+    /// Ignore `Flip` and `SetOrientation` while locked, so that the board
+    /// keeps its orientation until it is unlocked again.
+    LockOrientation(bool),
+    /// Choose how the board turns around when it is flipped. Defaults to
+    /// `FlipAnimation::Instant`, which is also used while animations are
+    /// disabled with `SetAnimationDuration(None)`.
+    SetFlipAnimation(FlipAnimation),
     /// Set up a position configuration.
     SetPos(Pos),
     /// Set up a board.
     SetBoard(Board),
+    /// Show the best move suggested by an engine, or `None` to hide it.
+    ///
+    /// The suggestion is drawn separately from user shapes and is cleared
+    /// whenever a new position or board is set.
+    SetBestMove(Option<(Square, Square)>),
+    /// Show the reply an engine is pondering on as a dashed arrow, or
+    /// `None` to hide it. Cleared whenever a new position or board is set.
+    SetPonderMove(Option<(Square, Square)>),
+    /// Show a line of play as a chain of arrows, shaded from dark for the
+    /// first move to light for the last move. An empty list hides it.
+    SetLine(Vec<(Square, Square)>),
+    /// Compare the given board to the pieces on the board, and tint
+    /// squares where pieces were added (green), removed (red) or changed
+    /// (orange). Cleared whenever a new position or board is set.
+    HighlightDiff(Board),
+    /// Tint the squares of the pieces giving check, in addition to the
+    /// glow around the king. Cleared whenever a new position or board is
+    /// set.
+    SetCheckers(Bitboard),
+    /// Tint the given squares with the colors of the brushes, for example
+    /// to mark a target or a mistake. Shown together with the last move.
+    /// An empty list removes them.
+    SetHighlights(Vec<(Square, DrawBrush)>),
+    /// Mark squares with small dots of the given colors (RGBA), for
+    /// example to overlay a heatmap. An empty list removes them.
+    SetDots(Vec<(Square, (f64, f64, f64, f64))>),
+    /// Draw a path through the centers of the given squares, with each
+    /// square numbered in order, for example to show a knight's tour. An
+    /// empty list hides it.
+    SetPath(Vec<Square>),
+    /// Preview a move without playing it, for example while a move in an
+    /// external move list is hovered. Draws an arrow and a small glyph of
+    /// the moving piece at the destination, or nothing if `None`.
+    PreviewMove(Option<(Square, Square, Piece)>),
+    /// Choose where to draw the coordinate labels.
+    SetCoordinatesStyle(CoordinatesStyle),
+    /// Show or hide the coordinate labels. The border stays in place.
+    SetCoordinates(bool),
+    /// Make promotions easier on touchscreens: tapping the destination
+    /// square again picks a queen as usual, but taps on the other choices
+    /// are ignored for a moment after the dialog opens, so that a hasty
+    /// double tap can not underpromote by accident. Disabled by default.
+    SetTapToQueen(bool),
+    /// Show the material balance in the border above and below the
+    /// board. Best combined with `CoordinatesStyle::Inline`, which keeps
+    /// the border free. Defaults to `MaterialBalance::Hidden`.
+    SetMaterial(MaterialBalance),
+    /// Draw coordinates in black or white instead of the theme colors, if
+    /// those contrast less with their background than the given ratio
+    /// (from 1 to 21, see WCAG). `None` (the default) always uses the
+    /// theme colors.
+    SetCoordinatesMinContrast(Option<f64>),
+    /// Switch to a high contrast theme for low vision users, or back to
+    /// the default theme.
+    SetHighContrast(bool),
+    /// Change the colors of the board and its highlights, for example to
+    /// a brown, blue or green palette.
+    SetTheme(BoardTheme),
+    /// Replace the piece images, for example with a set loaded with
+    /// `PieceSet::from_directory()`.
+    SetPieceSet(PieceSet),
+    /// Choose how to highlight the selected square.
+    SetSelectionStyle(SelectionStyle),
+    /// Choose how to show the legal destinations of the selected piece.
+    SetMoveHintStyle(MoveHintStyle),
+    /// Keep the moved piece selected on its destination square after a
+    /// move, for quickly chaining moves. Disabled by default.
+    SetKeepSelectionAfterMove(bool),
+    /// Set the distance in pixels the pointer has to travel before a press
+    /// on a piece starts a drag rather than a click. Defaults to 4.
+    SetDragThreshold(f64),
+    /// Set how long a press has to last before it can turn into a drag,
+    /// so that a jittery click does not flash the drag ghost. Defaults to
+    /// zero.
+    SetDragDelay(Duration),
+    /// Set how long pieces take to slide to their new squares, or `None`
+    /// to disable animations, including flips of the board and the turn
+    /// indicator. Defaults to 300 ms.
+    SetAnimationDuration(Option<Duration>),
+    /// Set how long the board takes to flip, or `None` to always flip at
+    /// once. Defaults to 400 ms.
+    SetFlipDuration(Option<Duration>),
+    /// Show the given piece under the pointer while dragging, instead of
+    /// the dragged piece itself, or `None` to show the dragged piece.
+    SetDragPieceOverride(Option<Piece>),
+    /// Choose how to show a piece on its original square while it is
+    /// dragged. Defaults to `DragGhostStyle::Faded`.
+    SetDragGhostStyle(DragGhostStyle),
+    /// Animate a dropped piece from where it was released to the center
+    /// of its square over the given duration, independently of other
+    /// moves. `None` (the default) snaps it immediately.
+    SetDropSnap(Option<Duration>),
+    /// Briefly flash the square when the user selects a piece without
+    /// legal moves. Disabled by default.
+    SetNoMovesCue(bool),
+    /// Cross-fade the whole board instead of animating individual pieces
+    /// when more than the given number of squares change at once, for
+    /// example when switching to an unrelated position. `None` (the
+    /// default) always animates individual pieces.
+    SetCrossfadeThreshold(Option<usize>),
+    /// Limit the number of pieces that can be fading out at the same
+    /// time. The oldest fades are completed immediately when the limit is
+    /// exceeded, for example while quickly scrubbing through a game.
+    /// Defaults to 32.
+    SetMaxFadingPieces(usize),
+    /// Tint captured pieces with the given color (RGBA) while they fade
+    /// out, or `None` (the default) to just fade them.
+    SetCaptureTint(Option<(f64, f64, f64, f64)>),
+    /// Fade out the last move highlight over the given duration after it
+    /// is set, or `None` (the default) to keep it until the next move.
+    SetLastMoveFade(Option<Duration>),
+    /// Animate the turn indicator when the side to move changes. Disabled
+    /// by default.
+    SetTurnAnimation(bool),
+    /// Handle scrolling over the board: scrolling up sends
+    /// `NavigateBack` and scrolling down sends `NavigateForward`, instead
+    /// of passing the scroll event on. Disabled by default.
+    SetScrollNavigates(bool),
+    /// Ignore clicks and pointer motion, so that the board only displays
+    /// positions. Pieces can not be moved and shapes can not be drawn.
+    /// Disabled by default.
+    SetViewOnly(bool),
+    /// Only let the user select and drag pieces of the given color, for
+    /// example when playing against an engine. `None` (the default) lets
+    /// the user move pieces of both colors.
+    SetMovableColor(Option<Color>),
+    /// Draw thin lines of the given color between the squares, or `None`
+    /// (the default) to disable them.
+    SetGridLines(Option<(f64, f64, f64, f64)>),
+    /// Outline the pieces in the given color, for contrast on busy or low
+    /// contrast boards, or `None` to draw them plain.
+    SetPieceOutline(Option<(f64, f64, f64, f64)>),
+    /// Round the size of the squares down to whole pixels, so that there
+    /// are no seams between them. The board becomes slightly smaller than
+    /// the allocation. Disabled by default.
+    SetPixelAlign(bool),
+    /// Bend arrows that would otherwise cover pieces on their way, so that
+    /// the pieces stay readable. Disabled by default.
+    SetCurvedArrows(bool),
+    /// Restrict the brushes the user can draw with. Modifier combinations
+    /// for other brushes fall back to the first allowed brush. An empty
+    /// list disables drawing.
+    SetAllowedBrushes(Vec<DrawBrush>),
+    /// Replace the shapes on the board, as if the user had drawn them, for
+    /// example to show an engine analysis. They can be erased like user
+    /// shapes, including by a left click if erasing on click is enabled.
+    SetShapes(Vec<DrawShape>),
+    /// Clear the shapes when the user left clicks the board. Enabled by
+    /// default. When disabled, shapes are only removed by drawing the same
+    /// shape again, or with `SetShapes` or `ClearShapes`.
+    SetEraseOnClick(bool),
+    /// Remove all shapes, including one that is currently being drawn,
+    /// for example when navigating to another position. Sends
+    /// `ShapesChanged`.
+    ClearShapes,
+    /// Show authored shapes, for example the arrows of a study. Unlike
+    /// user shapes they can not be erased or toggled by the user, and
+    /// they persist until replaced.
+    SetStickyShapes(Vec<DrawShape>),
+    /// Choose what happens when the user draws a shape that already
+    /// exists. Defaults to `ShapeToggle::Toggle`.
+    SetShapeToggle(ShapeToggle),
+    /// Queue a premove, for example to restore it after the board was
+    /// recreated. It is played or cancelled like a premove of the user.
+    SetPremove(Square, Square),
+    /// Cancel the queued premove, if any, without sending
+    /// `PremoveCancelled`.
+    ClearPremove,
+    /// Set the color used to highlight a queued premove.
+    SetPremoveColor((f64, f64, f64, f64)),
+    /// Fill the entire widget with the given color before drawing the
+    /// board, so that the area around a board in a non-square allocation
+    /// is not left to whatever was painted before. `None` (the default)
+    /// leaves it transparent.
+    SetClearBackground(Option<(f64, f64, f64, f64)>),
+    /// Draw the pieces on the given squares upside down, for example to
+    /// mark them as lost in a study.
+    SetFlippedPieces(Bitboard),
+    /// Do not draw the pieces on the given squares, for example for fog of
+    /// war variants. The pieces are still part of the position, but can
+    /// not be selected or dragged, and do not count for the material
+    /// balance.
+    SetHiddenPieces(Bitboard),
+    /// Faintly highlight the file and rank of the hovered square, to help
+    /// beginners read coordinates. Disabled by default.
+    SetCoordinateRuler(bool),
+    /// Replace the clock that drives animations, for example with a
+    /// `ManualClock` to step through them deterministically in tests.
+    SetClock(Rc<dyn Clock>),
+    /// Instantly finish all running animations.
+    SettleAnimations,
+    /// Apply several messages at once, with a single redraw at the end.
+    /// Events are still sent as usual.
+    Batch(Vec<GroundMsg>),
+    /// Keep the given number of pixels free between the edges of the
+    /// widget and the board. Defaults to 0.
+    SetPadding(i32),
+    /// Redraw the board, for example after external state that affects
+    /// the rendering has changed.
+    Redraw,
 
     /// Sent when the completed a piece drag or move.
     UserMove(Square, Square, Option<Role>),
+    /// Sent after `UserMove` if the move is one of the legal moves set
+    /// with `SetPos`. Carries the full move, so that castling, en passant
+    /// and captures can be told apart without looking it up again.
+    MoveMade(Move),
     /// Sent when shapes are added, removed or cleared.
     ShapesChanged(Vec<DrawShape>),
+    /// Sent while the user draws a shape, with the shape so far whenever
+    /// it changes, and `None` when the mouse button is released. The
+    /// result is then sent with `ShapesChanged`.
+    ShapeDrawing(Option<DrawShape>),
+    /// Sent when the user queued a premove by moving a piece of the side
+    /// that is not to move. It is played with `UserMove` as soon as it
+    /// becomes legal after the next `SetPos`.
+    PremoveSet(Square, Square),
+    /// Sent when a queued premove was cancelled, either by a click or
+    /// because it was not legal.
+    PremoveCancelled,
+    /// Sent after `SetPos` when the last move hint describes a move that
+    /// was just played on the displayed board, so that a matching sound
+    /// can be played.
+    MoveSound(SoundKind),
+    /// Sent when the check hint changes, with the square of the king in
+    /// check.
+    CheckChanged(Option<Square>),
+    /// Sent when the user scrolls up over the board, if enabled with
+    /// `SetScrollNavigates`, to go back in the game.
+    NavigateBack,
+    /// Sent when the user scrolls down over the board, if enabled with
+    /// `SetScrollNavigates`, to go forward in the game.
+    NavigateForward,
+    /// Sent when the user double clicks a square. Both clicks are also
+    /// handled as single clicks before.
+    SquareDoubleClicked(Square),
+    /// Sent when the promotion dialog opens for a move, with the color of
+    /// the promoting pawn.
+    PromotionStarted(Square, Square, Color),
+    /// Sent when the promotion dialog closes without a choice, either by a
+    /// click elsewhere or because the move is no longer legal.
+    PromotionCancelled,
+    /// Sent when the user selects a piece by clicking or dragging it, with
+    /// `None` when the selection is cleared.
+    SelectionChanged(Option<Square>),
+    /// Sent when `Flip` or `SetOrientation` changed the orientation, and
+    /// in reply to `RequestOrientation`, with the side at the bottom.
+    OrientationChanged(Color),
+}
+
+/// Kind of sound to play for a move.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SoundKind {
+    /// A quiet move.
+    Move,
+    /// A capture, including en passant.
+    Capture,
+    /// A move that gives check.
+    Check,
+    /// Castling.
+    Castle,
 }
 
 /// A position configuration.
@@ -78,6 +373,7 @@ pub struct Pos {
     legals: Box<MoveList>,
     check: Option<Square>,
     last_move: Option<(Square, Square)>,
+    last_move_extra: Bitboard,
     turn: Option<Color>,
 }
 
@@ -89,6 +385,7 @@ impl Pos {
             legals: Box::new(p.legal_moves()),
             check: if p.checkers().any() { p.board().king_of(p.turn()) } else { None },
             last_move: None,
+            last_move_extra: Bitboard(0),
             turn: Some(p.turn()),
         }
     }
@@ -100,14 +397,44 @@ impl Pos {
             legals: Box::new(MoveList::new()),
             check: None,
             last_move: None,
+            last_move_extra: Bitboard(0),
             turn: None,
         }
     }
 
+    /// Create a position configuration from the pieces on each square,
+    /// without any other hints. Squares are indexed from a1 = 0, b1 = 1,
+    /// and so on, up to h8 = 63.
+    ///
+    /// Useful for setting up the board with `GroundMsg::SetPos` without
+    /// building a `Board` first.
+    pub fn from_squares(pieces: [Option<Piece>; 64]) -> Pos {
+        let mut board = Board::empty();
+        for (&square, &piece) in Square::ALL.iter().zip(pieces.iter()) {
+            if let Some(piece) = piece {
+                board.set_piece_at(square, piece);
+            }
+        }
+        Pos::from_board(board)
+    }
+
     /// Set the hint for the last move, so that it can be highlighted on
-    /// the board.
+    /// the board. Drops are marked on their destination square only.
+    /// Castling highlights the squares of both the king and the rook, and
+    /// en passant the square of the captured pawn.
     pub fn set_last_move(&mut self, m: Option<&Move>) {
-        self.last_move = m.map(|m| (m.from().unwrap_or_else(|| m.to()), m.to()))
+        self.last_move = m.map(|m| (m.from().unwrap_or_else(|| m.to()), m.to()));
+        self.last_move_extra = match m {
+            Some(&Move::Castle { king, rook }) => {
+                // the king and rook end up on the same files as in
+                // standard chess, also in chess960
+                let (king_to, rook_to) = if rook < king { (File::C, File::D) } else { (File::G, File::F) };
+                Bitboard::from_square(Square::from_coords(king_to, king.rank())) |
+                Bitboard::from_square(Square::from_coords(rook_to, king.rank()))
+            }
+            Some(&Move::EnPassant { from, to }) => Bitboard::from_square(Square::from_coords(to.file(), from.rank())),
+            _ => Bitboard(0),
+        };
     }
 
     pub fn with_last_move(mut self, m: &Move) -> Self {
@@ -144,14 +471,121 @@ impl Pos {
         self.turn = Some(turn);
         self
     }
+
+    /// Check that the hints are consistent with the board.
+    ///
+    /// Inconsistent hints are rendered anyway, so it can be useful to
+    /// validate positions in debug builds before sending them with
+    /// `SetPos`.
+    pub fn validate(&self) -> Result<(), PosError> {
+        if let Some(check) = self.check {
+            match self.board.piece_at(check) {
+                Some(piece) if piece.role == Role::King => {
+                    if self.turn.map_or(false, |turn| turn != piece.color) {
+                        return Err(PosError::CheckOnWrongSide(check));
+                    }
+                }
+                _ => return Err(PosError::CheckWithoutKing(check)),
+            }
+        }
+
+        for m in self.legals.iter() {
+            if let Some(from) = m.from() {
+                match self.board.piece_at(from) {
+                    Some(piece) => {
+                        if self.turn.map_or(false, |turn| turn != piece.color) {
+                            return Err(PosError::LegalMoveOfWrongSide(m.clone()));
+                        }
+                    }
+                    None => return Err(PosError::LegalMoveFromEmptySquare(m.clone())),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Error when a position configuration is inconsistent.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PosError {
+    /// The check hint is not on a king.
+    CheckWithoutKing(Square),
+    /// The check hint is on the king of the side that is not to move.
+    CheckOnWrongSide(Square),
+    /// A legal move hint starts on an empty square.
+    LegalMoveFromEmptySquare(Move),
+    /// A legal move hint moves a piece of the side that is not to move.
+    LegalMoveOfWrongSide(Move),
+}
+
+impl fmt::Display for PosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PosError::CheckWithoutKing(sq) => write!(f, "check hint on {} without king", sq),
+            PosError::CheckOnWrongSide(sq) => write!(f, "check hint on {} for side not to move", sq),
+            PosError::LegalMoveFromEmptySquare(ref m) => write!(f, "legal move {:?} from empty square", m),
+            PosError::LegalMoveOfWrongSide(ref m) => write!(f, "legal move {:?} for side not to move", m),
+        }
+    }
 }
 
+impl Error for PosError {}
+
 impl Default for Pos {
     fn default() -> Pos {
         Pos::new(&Chess::default())
     }
 }
 
+/// How the board reacts to the user, applied at once with
+/// `GroundMsg::SetInteraction`.
+///
+/// Start from `InteractionConfig::default()`, which matches the behavior
+/// of a new board, and change the fields of interest, so that code keeps
+/// working when more options are added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteractionConfig {
+    /// Let the user draw shapes with the right mouse button.
+    pub draw_shapes: bool,
+    /// Clear the drawn shapes on a left click.
+    pub erase_shapes_on_click: bool,
+    /// What happens when the user draws a shape that already exists.
+    pub shape_toggle: ShapeToggle,
+    /// Keep the moved piece selected after a move.
+    pub keep_selection_after_move: bool,
+    /// Distance in pixels the pointer has to travel before a drag starts.
+    pub drag_threshold: f64,
+    /// Time the button has to be held before a drag starts.
+    pub drag_delay: Duration,
+    /// Briefly flash the square of a selected piece without legal moves.
+    pub no_moves_cue: bool,
+    /// Send `NavigateBack` and `NavigateForward` on scroll events.
+    pub scroll_navigates: bool,
+    /// Ignore clicks and pointer motion.
+    pub view_only: bool,
+    /// Only let the user move pieces of this color, or of both colors if
+    /// `None`.
+    pub movable_color: Option<Color>,
+}
+
+impl Default for InteractionConfig {
+    fn default() -> InteractionConfig {
+        InteractionConfig {
+            draw_shapes: true,
+            erase_shapes_on_click: true,
+            shape_toggle: ShapeToggle::Toggle,
+            keep_selection_after_move: false,
+            drag_threshold: 4.0,
+            drag_delay: Duration::zero(),
+            no_moves_cue: false,
+            scroll_navigates: false,
+            view_only: false,
+            movable_color: None,
+        }
+    }
+}
+
 /// Chessground, a chess board widget.
 #[derive(Debug)]
 pub struct Ground {
@@ -159,55 +593,570 @@ pub struct Ground {
     model: Model,
 }
 
+impl Ground {
+    fn queue_draw(&self) {
+        // redraw once at the end of a batch
+        if !self.model.batching {
+            self.drawing_area.queue_draw();
+        }
+    }
+
+    /// Call the given function with every message the board sends, like
+    /// `UserMove` or `ShapesChanged`, before it is delivered, for example
+    /// to log them while debugging an integration. `None` removes it.
+    pub fn set_message_tap(&self, tap: Option<Box<dyn Fn(&GroundMsg)>>) {
+        *self.model.stream.tap.borrow_mut() = tap;
+    }
+
+    /// The piece currently displayed on a square.
+    pub fn piece_at(&self, square: Square) -> Option<Piece> {
+        self.model.state.borrow().pieces.figurine_at(square).map(|f| *f.piece())
+    }
+
+    /// The animation state of every piece on the board, including pieces
+    /// that are fading out. Poll it on each tick to keep external
+    /// animations in sync with the board.
+    pub fn figurine_states(&self) -> Vec<FigurineState> {
+        let state = self.model.state.borrow();
+        state.pieces.figurine_states(state.clock.now())
+    }
+
+    /// The legal moves currently loaded with `SetPos`.
+    pub fn legals(&self) -> MoveList {
+        self.model.state.borrow().board_state.legals().clone()
+    }
+
+    /// The current interaction options, including those set with
+    /// individual messages.
+    pub fn interaction(&self) -> InteractionConfig {
+        self.model.state.borrow().interaction()
+    }
+
+    /// The square of the king in check, as set with `SetPos`.
+    pub fn check(&self) -> Option<Square> {
+        self.model.state.borrow().board_state.check()
+    }
+
+    /// Squares currently occupied by pieces of the given color.
+    pub fn occupied_by(&self, color: Color) -> Bitboard {
+        self.model.state.borrow().pieces.occupied_by(color)
+    }
+
+    /// The center of a square in pixels relative to the allocation of the
+    /// widget, taking the orientation into account. Useful for drawing
+    /// overlays that connect several boards.
+    pub fn square_to_widget_coords(&self, square: Square) -> (f64, f64) {
+        let state = self.model.state.borrow();
+        let ctx = WidgetContext::new(&state.board_state, &self.drawing_area, state.padding, state.clock.now());
+        ctx.pos_to_widget_coords(square_to_pos(square))
+    }
+
+    /// Whether there is a piece under the given point in pixels relative
+    /// to the allocation of the widget, for example to show a grab cursor.
+    pub fn piece_under(&self, x: f64, y: f64) -> bool {
+        let state = self.model.state.borrow();
+        let ctx = WidgetContext::new(&state.board_state, &self.drawing_area, state.padding, state.clock.now());
+        pos_to_square(ctx.widget_coords_to_pos((x, y))).map_or(false, |square| {
+            state.pieces.occupied().contains(square)
+        })
+    }
+
+    /// Export the current board as a single page PDF document for
+    /// printing, with the given side length in millimeters. Squares,
+    /// coordinates and pieces are kept as vector graphics. The document
+    /// shows the final frame of running animations.
+    pub fn export_pdf<P: AsRef<Path>>(&self, path: P, size_mm: f64) -> Result<(), cairo::Error> {
+        // document units are points of 1/72 inch
+        let size = size_mm / 25.4 * 72.0;
+        let surface = PdfSurface::new(size, size, path)?;
+
+        {
+            // render in finer units and scale them to the page
+            let units = 900;
+            let cr = Context::new(&surface)?;
+            cr.scale(size / f64::from(units), size / f64::from(units));
+            self.model.state.borrow().settled().render(&cr, units, units)?;
+            cr.show_page()?;
+        }
+
+        surface.finish();
+        Ok(())
+    }
+
+    /// Export the current board as an SVG image with the given side length,
+    /// for example for diagrams. The image shows the final frame of running
+    /// animations, which keep running on the board itself.
+    pub fn export_svg<P: AsRef<Path>>(&self, path: P, size: f64) -> Result<(), cairo::Error> {
+        let surface = SvgSurface::new(size, size, Some(path))?;
+
+        {
+            let state = self.model.state.borrow().settled();
+
+            let units = 900;
+            let cr = Context::new(&surface)?;
+            cr.scale(size / f64::from(units), size / f64::from(units));
+            state.render(&cr, units, units)?;
+        }
+
+        surface.finish();
+        Ok(())
+    }
+
+    /// Export the current board as a square PNG image with the given side
+    /// length, for example for puzzle thumbnails. Like `export_svg()`, the
+    /// image shows the final frame of running animations.
+    pub fn export_png<P: AsRef<Path>>(&self, path: P, size: i32) -> Result<(), cairo::IoError> {
+        let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+
+        {
+            let state = self.model.state.borrow().settled();
+            let cr = Context::new(&surface)?;
+            state.render(&cr, size, size)?;
+        }
+
+        let mut file = fs::File::create(path)?;
+        surface.write_to_png(&mut file)?;
+        Ok(())
+    }
+
+    /// Render the current board into a square pixbuf with the given side
+    /// length, for example to copy it to the clipboard. Like
+    /// `export_png()`, the image shows the final frame of running
+    /// animations.
+    pub fn render_pixbuf(&self, size: i32) -> Result<Pixbuf, cairo::Error> {
+        let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+
+        {
+            let state = self.model.state.borrow().settled();
+            let cr = Context::new(&surface)?;
+            state.render(&cr, size, size)?;
+        }
+
+        // gdk gives no reason when the conversion fails
+        gdk::pixbuf_get_from_surface(&surface, 0, 0, size, size).ok_or(cairo::Error::NoMemory)
+    }
+}
+
+/// Render a position configuration into a square image with the given side
+/// length, without a widget.
+///
+/// If `promotion` is given, the promotion dialog for the move between the
+/// two squares is open. This is mainly useful for tests of the rendering.
+pub fn render_pos(pos: &Pos, orientation: Color, promotion: Option<(Square, Square)>, size: i32) -> Result<ImageSurface, cairo::Error> {
+    let mut state = State::new();
+    let now = state.clock.now();
+
+    state.pieces = Pieces::new_from_board(&pos.board, now);
+    state.board_state.set_orientation(orientation, now);
+    state.board_state.set_check(pos.check);
+    state.board_state.set_last_move(pos.last_move, pos.last_move_extra, now);
+    state.board_state.set_turn(pos.turn, now);
+    *state.board_state.legals_mut() = (*pos.legals).clone();
+
+    if let Some((orig, dest)) = promotion {
+        let color = pos.board.piece_at(orig).map_or_else(|| {
+            Color::from_white(dest.rank() > Rank::Fourth)
+        }, |piece| piece.color);
+        state.promotable.start(color, orig, dest, now);
+    }
+
+    let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+
+    {
+        let cr = Context::new(&surface)?;
+        state.render(&cr, size, size)?;
+    }
+
+    Ok(surface)
+}
+
 impl Update for Ground {
     type Model = Model;
     type ModelParam = ();
     type Msg = GroundMsg;
 
-    fn model(_: &Relm<Self>, _: ()) -> Model {
+    fn model(relm: &Relm<Self>, _: ()) -> Model {
         Model {
             state: Rc::new(RefCell::new(State::new())),
+            stream: Stream {
+                handle: relm.stream().clone(),
+                tap: Rc::new(RefCell::new(None)),
+            },
+            batching: false,
         }
     }
 
     fn update(&mut self, event: GroundMsg) {
+        if let GroundMsg::Batch(events) = event {
+            // apply all messages, then redraw once
+            let batching = mem::replace(&mut self.model.batching, true);
+            for event in events {
+                self.update(event);
+            }
+            self.model.batching = batching;
+            self.queue_draw();
+            return;
+        }
+
         let mut state = self.model.state.borrow_mut();
 
         match event {
             GroundMsg::Flip => {
-                let orientation = state.board_state.orientation();
-                state.board_state.set_orientation(!orientation);
-                self.drawing_area.queue_draw();
+                let (orientation, now) = (state.board_state.orientation(), state.clock.now());
+                state.board_state.set_orientation(!orientation, now);
+                if state.board_state.orientation() != orientation {
+                    self.model.stream.emit(GroundMsg::OrientationChanged(!orientation));
+                }
+                self.queue_draw();
             },
             GroundMsg::SetOrientation(orientation) => {
-                state.board_state.set_orientation(orientation);
-                self.drawing_area.queue_draw();
+                let (previous, now) = (state.board_state.orientation(), state.clock.now());
+                state.board_state.set_orientation(orientation, now);
+                if state.board_state.orientation() != previous {
+                    self.model.stream.emit(GroundMsg::OrientationChanged(orientation));
+                }
+                self.queue_draw();
+            },
+            GroundMsg::RequestOrientation => {
+                self.model.stream.emit(GroundMsg::OrientationChanged(state.board_state.orientation()));
+            },
+            GroundMsg::LockOrientation(locked) => {
+                state.board_state.set_orientation_locked(locked);
+            },
+            GroundMsg::SetInteraction(config) => {
+                state.set_interaction(&self.model.stream, config);
+                self.queue_draw();
+            },
+            GroundMsg::SetFlipAnimation(flip_animation) => {
+                state.board_state.set_flip_animation(flip_animation);
+                self.queue_draw();
+            },
+            GroundMsg::SetPos(ref pos) if state.shows(pos) => {
+                // the same position was sent again, so there is nothing to
+                // animate or redraw
             },
             GroundMsg::SetPos(pos) => {
-                state.pieces.set_board(&pos.board);
-                state.promotable.update(&pos.legals);
+                // classify the move before the pieces are updated
+                let sound = pos.last_move.and_then(|(orig, dest)| {
+                    let piece = *state.pieces.figurine_at(orig)?.piece();
+                    if pos.board.piece_at(orig) == Some(piece) {
+                        return None;
+                    }
+
+                    let captured = state.pieces.figurine_at(dest).map(|f| *f.piece());
+                    Some(if pos.check.is_some() {
+                        SoundKind::Check
+                    } else if piece.role == Role::King && (captured.map_or(false, |c| c.color == piece.color) || orig.distance(dest) > 1) {
+                        SoundKind::Castle
+                    } else if captured.is_some() || (piece.role == Role::Pawn && orig.file() != dest.file()) {
+                        SoundKind::Capture
+                    } else {
+                        SoundKind::Move
+                    })
+                });
+
+                let now = state.clock.now();
+                state.pieces.set_board(&pos.board, pos.last_move, now);
+                if state.promotable.update(&pos.legals) {
+                    self.model.stream.emit(GroundMsg::PromotionCancelled);
+                }
+                if state.board_state.check() != pos.check {
+                    self.model.stream.emit(GroundMsg::CheckChanged(pos.check));
+                }
                 state.board_state.set_check(pos.check);
-                state.board_state.set_last_move(pos.last_move);
-                state.board_state.set_turn(pos.turn);
+                state.board_state.set_checkers(Bitboard(0));
+                state.board_state.set_last_move(pos.last_move, pos.last_move_extra, now);
+                state.board_state.set_turn(pos.turn, now);
                 *state.board_state.legals_mut() = *pos.legals;
-                self.drawing_area.queue_draw();
+                state.drawable.set_best_move(None);
+                state.drawable.set_diff(None);
+                state.drawable.set_ponder_move(None);
+
+                if let Some(sound) = sound {
+                    self.model.stream.emit(GroundMsg::MoveSound(sound));
+                }
+
+                // play or cancel the premove once it is our turn. cancel
+                // it right away if the premoved piece was captured or
+                // replaced.
+                if let Some((orig, dest, piece)) = state.pieces.premove() {
+                    if pos.board.piece_at(orig) != Some(piece) {
+                        state.pieces.clear_premove();
+                        self.model.stream.emit(GroundMsg::PremoveCancelled);
+                    } else if pos.turn == Some(piece.color) {
+                        state.pieces.clear_premove();
+                        // the legal moves of the new position were just
+                        // loaded into the board state
+                        let legal = state.board_state.legals().iter().any(|m| {
+                            m.from() == Some(orig) && m.to() == dest && m.role() == piece.role
+                        });
+                        if legal {
+                            self.model.stream.emit(GroundMsg::UserMove(orig, dest, None));
+                        } else {
+                            self.model.stream.emit(GroundMsg::PremoveCancelled);
+                        }
+                    }
+                }
+
+                self.queue_draw();
             },
             GroundMsg::SetBoard(board) => {
-                state.pieces.set_board(&board);
+                let now = state.clock.now();
+                state.pieces.set_board(&board, None, now);
+                if state.board_state.check().is_some() {
+                    self.model.stream.emit(GroundMsg::CheckChanged(None));
+                }
                 state.board_state.set_check(None);
-                state.board_state.set_last_move(None);
-                state.board_state.set_turn(None);
+                state.board_state.set_checkers(Bitboard(0));
+                state.board_state.set_last_move(None, Bitboard(0), now);
+                state.board_state.set_turn(None, now);
                 state.board_state.legals_mut().clear();
-                state.promotable.cancel();
-                self.drawing_area.queue_draw();
+                if state.promotable.cancel() {
+                    self.model.stream.emit(GroundMsg::PromotionCancelled);
+                }
+                state.drawable.set_best_move(None);
+                state.drawable.set_diff(None);
+                state.drawable.set_ponder_move(None);
+                if state.pieces.premove().is_some() {
+                    state.pieces.clear_premove();
+                    self.model.stream.emit(GroundMsg::PremoveCancelled);
+                }
+                self.queue_draw();
+            },
+            GroundMsg::SetBestMove(best_move) => {
+                state.drawable.set_best_move(best_move);
+                self.queue_draw();
+            },
+            GroundMsg::SetPonderMove(ponder_move) => {
+                state.drawable.set_ponder_move(ponder_move);
+                self.queue_draw();
+            },
+            GroundMsg::SetLine(line) => {
+                state.drawable.set_line(line);
+                self.queue_draw();
+            },
+            GroundMsg::HighlightDiff(board) => {
+                let diff = state.pieces.diff(&board);
+                state.drawable.set_diff(Some(diff));
+                self.queue_draw();
+            },
+            GroundMsg::SetCheckers(checkers) => {
+                state.board_state.set_checkers(checkers);
+                self.queue_draw();
+            },
+            GroundMsg::SetHighlights(highlights) => {
+                state.board_state.set_highlights(highlights);
+                self.queue_draw();
+            },
+            GroundMsg::SetDots(dots) => {
+                state.drawable.set_dots(dots);
+                self.queue_draw();
+            },
+            GroundMsg::SetPath(path) => {
+                state.drawable.set_path(path);
+                self.queue_draw();
+            },
+            GroundMsg::PreviewMove(preview) => {
+                state.drawable.set_preview(preview);
+                self.queue_draw();
+            },
+            GroundMsg::SetCoordinates(show_coordinates) => {
+                state.board_state.set_show_coordinates(show_coordinates);
+                self.queue_draw();
+            },
+            GroundMsg::SetCoordinatesStyle(style) => {
+                state.board_state.set_coordinates_style(style);
+                self.queue_draw();
+            },
+            GroundMsg::SetTapToQueen(tap_to_queen) => {
+                state.promotable.set_tap_to_queen(tap_to_queen);
+            },
+            GroundMsg::SetMaterial(material) => {
+                state.board_state.set_material(material);
+                self.queue_draw();
+            },
+            GroundMsg::SetCoordinatesMinContrast(min_contrast) => {
+                state.board_state.set_coordinates_min_contrast(min_contrast);
+                self.queue_draw();
+            },
+            GroundMsg::SetTheme(theme) => {
+                state.board_state.set_theme(theme);
+                self.queue_draw();
+            },
+            GroundMsg::SetPieceSet(piece_set) => {
+                state.board_state.set_piece_set(piece_set);
+                self.queue_draw();
+            },
+            GroundMsg::SetHighContrast(high_contrast) => {
+                state.board_state.set_theme(if high_contrast {
+                    BoardTheme::high_contrast()
+                } else {
+                    BoardTheme::default()
+                });
+                self.queue_draw();
+            },
+            GroundMsg::SetSelectionStyle(style) => {
+                state.pieces.set_selection_style(style);
+                self.queue_draw();
+            },
+            GroundMsg::SetMoveHintStyle(style) => {
+                state.pieces.set_move_hint_style(style);
+                self.queue_draw();
+            },
+            GroundMsg::SetKeepSelectionAfterMove(keep) => {
+                state.pieces.set_keep_selection_after_move(keep);
+            },
+            GroundMsg::SetDragThreshold(threshold) => {
+                state.pieces.set_drag_threshold(threshold);
+            },
+            GroundMsg::SetDragDelay(delay) => {
+                state.pieces.set_drag_delay(delay);
             },
-            GroundMsg::UserMove(orig, dest, None) if state.board_state.valid_move(orig, dest) => {
-                if state.board_state.legals().iter().any(|m| m.from() == Some(orig) && m.to() == dest && m.promotion().is_some()) {
+            GroundMsg::SetAnimationDuration(duration) => {
+                state.pieces.set_animation_duration(duration);
+                state.board_state.set_animated(duration.is_some());
+            },
+            GroundMsg::SetFlipDuration(duration) => {
+                state.board_state.set_flip_duration(duration);
+            },
+            GroundMsg::SetDragPieceOverride(piece) => {
+                state.pieces.set_drag_piece_override(piece);
+                self.queue_draw();
+            },
+            GroundMsg::SetDragGhostStyle(style) => {
+                state.pieces.set_drag_ghost_style(style);
+            },
+            GroundMsg::SetDropSnap(duration) => {
+                state.pieces.set_drop_snap(duration);
+            },
+            GroundMsg::SetNoMovesCue(enabled) => {
+                state.pieces.set_blocked_cue(enabled);
+            },
+            GroundMsg::SetCrossfadeThreshold(threshold) => {
+                state.pieces.set_crossfade_threshold(threshold);
+            },
+            GroundMsg::SetCaptureTint(tint) => {
+                state.pieces.set_capture_tint(tint);
+            },
+            GroundMsg::SetMaxFadingPieces(max_fading) => {
+                state.pieces.set_max_fading(max_fading);
+            },
+            GroundMsg::SetScrollNavigates(scroll_navigates) => {
+                state.scroll_navigates = scroll_navigates;
+            },
+            GroundMsg::SetViewOnly(view_only) => {
+                state.set_view_only(&self.model.stream, view_only);
+                self.queue_draw();
+            },
+            GroundMsg::SetMovableColor(movable) => {
+                state.pieces.set_movable(movable);
+            },
+            GroundMsg::SetTurnAnimation(turn_animation) => {
+                state.board_state.set_turn_animation(turn_animation);
+            },
+            GroundMsg::SetLastMoveFade(fade) => {
+                state.board_state.set_last_move_fade(fade);
+                self.queue_draw();
+            },
+            GroundMsg::SetGridLines(grid_lines) => {
+                state.board_state.set_grid_lines(grid_lines);
+                self.queue_draw();
+            },
+            GroundMsg::SetShapeToggle(toggle) => {
+                state.drawable.set_toggle(toggle);
+            },
+            GroundMsg::SetShapes(shapes) => {
+                state.drawable.set_shapes(shapes);
+                self.queue_draw();
+            },
+            GroundMsg::SetEraseOnClick(erase_on_click) => {
+                state.drawable.set_erase_on_click(erase_on_click);
+            },
+            GroundMsg::ClearShapes => {
+                state.drawable.clear_shapes();
+                self.model.stream.emit(GroundMsg::ShapesChanged(Vec::new()));
+                self.queue_draw();
+            },
+            GroundMsg::SetStickyShapes(shapes) => {
+                state.drawable.set_sticky_shapes(shapes);
+                self.queue_draw();
+            },
+            GroundMsg::SetAllowedBrushes(brushes) => {
+                state.drawable.set_allowed_brushes(brushes);
+            },
+            GroundMsg::SetCurvedArrows(curved_arrows) => {
+                state.drawable.set_curved_arrows(curved_arrows);
+                self.queue_draw();
+            },
+            GroundMsg::SetPixelAlign(pixel_align) => {
+                state.board_state.set_pixel_align(pixel_align);
+                self.queue_draw();
+            },
+            GroundMsg::SetPieceOutline(outline) => {
+                state.board_state.set_piece_outline(outline);
+                self.queue_draw();
+            },
+            GroundMsg::SetPremove(orig, dest) => {
+                let piece = state.pieces.figurine_at(orig).map(|f| *f.piece());
+                state.pieces.set_premove(piece.map(|piece| (orig, dest, piece)));
+                self.queue_draw();
+            },
+            GroundMsg::ClearPremove => {
+                state.pieces.clear_premove();
+                self.queue_draw();
+            },
+            GroundMsg::SetPremoveColor(color) => {
+                state.pieces.set_premove_color(color);
+                self.queue_draw();
+            },
+            GroundMsg::SetClearBackground(color) => {
+                state.clear_background = color;
+                self.queue_draw();
+            },
+            GroundMsg::SetCoordinateRuler(coordinate_ruler) => {
+                state.pieces.set_coordinate_ruler(coordinate_ruler);
+                self.queue_draw();
+            },
+            GroundMsg::SetHiddenPieces(hidden) => {
+                state.pieces.set_hidden(hidden);
+                self.queue_draw();
+            },
+            GroundMsg::SetFlippedPieces(flipped) => {
+                state.pieces.set_flipped(flipped);
+                self.queue_draw();
+            },
+            GroundMsg::SettleAnimations => {
+                state.board_state.settle();
+                state.pieces.settle();
+                state.promotable.settle();
+                self.queue_draw();
+            },
+            GroundMsg::SetPadding(padding) => {
+                state.padding = padding;
+                self.queue_draw();
+            },
+            GroundMsg::Redraw => {
+                self.queue_draw();
+            },
+            GroundMsg::SetClock(clock) => {
+                state.clock = clock;
+                self.queue_draw();
+            },
+            GroundMsg::UserMove(orig, dest, promotion) => {
+                let legal = state.board_state.legals().iter().find(|m| {
+                    m.from() == Some(orig) && m.to() == dest && m.promotion() == promotion
+                }).cloned();
+
+                if let Some(m) = legal {
+                    self.model.stream.emit(GroundMsg::MoveMade(m));
+                } else if promotion.is_none() && state.board_state.legals().iter().any(|m| m.from() == Some(orig) && m.to() == dest && m.promotion().is_some()) {
                     let color = state.pieces.figurine_at(orig).map_or_else(|| {
                         Color::from_white(dest.rank() > Rank::Fourth)
                     }, |figurine| figurine.piece().color);
-                    state.promotable.start(color, orig, dest);
-                    self.drawing_area.queue_draw();
+                    let now = state.clock.now();
+                    state.promotable.start(color, orig, dest, now);
+                    self.model.stream.emit(GroundMsg::PromotionStarted(orig, dest, color));
+                    self.queue_draw();
                 }
             },
             _ => {}
@@ -222,12 +1171,13 @@ impl Widget for Ground {
         self.drawing_area.clone()
     }
 
-    fn view(relm: &Relm<Self>, model: Model) -> Self {
+    fn view(_: &Relm<Self>, model: Model) -> Self {
         let drawing_area = DrawingArea::new();
 
         drawing_area.add_events(EventMask::BUTTON_PRESS_MASK |
                                 EventMask::BUTTON_RELEASE_MASK |
                                 EventMask::POINTER_MOTION_MASK |
+                                EventMask::LEAVE_NOTIFY_MASK |
                                 EventMask::SCROLL_MASK);
 
         {
@@ -238,15 +1188,20 @@ impl Widget for Ground {
                     let state = state.borrow();
                     state.draw(widget, cr).unwrap();
 
-                    // queue next draw for animation
-                    let weak_state = Weak::clone(&weak_state);
-                    let widget = widget.clone();
-                    cairo::glib::idle_add_local(move || {
-                        if let Some(state) = weak_state.upgrade() {
-                            state.borrow_mut().queue_animation(&widget);
-                        }
-                        Continue(false)
-                    });
+                    // queue next draw for animation, unless the widget is
+                    // hidden and would not be drawn anyway
+                    if widget.is_mapped() {
+                        let weak_state = Weak::clone(&weak_state);
+                        let widget = widget.clone();
+                        cairo::glib::idle_add_local(move || {
+                            if let Some(state) = weak_state.upgrade() {
+                                if widget.is_mapped() {
+                                    state.borrow_mut().queue_animation(&widget);
+                                }
+                            }
+                            Continue(false)
+                        });
+                    }
                 }
                 Inhibit(false)
             });
@@ -255,7 +1210,7 @@ impl Widget for Ground {
         {
             // mouse down
             let state = Rc::downgrade(&model.state);
-            let stream = relm.stream().clone();
+            let stream = model.stream.clone();
             drawing_area.connect_button_press_event(move |widget, e| {
                 if let Some(state) = state.upgrade() {
                     let mut state = state.borrow_mut();
@@ -268,7 +1223,7 @@ impl Widget for Ground {
         {
             // mouse up
             let state = Rc::downgrade(&model.state);
-            let stream = relm.stream().clone();
+            let stream = model.stream.clone();
             drawing_area.connect_button_release_event(move |widget, e| {
                 if let Some(state) = state.upgrade() {
                     let mut state = state.borrow_mut();
@@ -281,7 +1236,7 @@ impl Widget for Ground {
         {
             // mouse move
             let state = Rc::downgrade(&model.state);
-            let stream = relm.stream().clone();
+            let stream = model.stream.clone();
             drawing_area.connect_motion_notify_event(move |widget, e| {
                 if let Some(state) = state.upgrade() {
                     let mut state = state.borrow_mut();
@@ -291,6 +1246,31 @@ impl Widget for Ground {
             });
         }
 
+        {
+            // mouse leave
+            let state = Rc::downgrade(&model.state);
+            drawing_area.connect_leave_notify_event(move |widget, _| {
+                if let Some(state) = state.upgrade() {
+                    let mut state = state.borrow_mut();
+                    state.leave_notify_event(widget);
+                }
+                Inhibit(false)
+            });
+        }
+
+        {
+            // scroll
+            let state = Rc::downgrade(&model.state);
+            let stream = model.stream.clone();
+            drawing_area.connect_scroll_event(move |_, e| {
+                if let Some(state) = state.upgrade() {
+                    let state = state.borrow();
+                    return state.scroll_event(&stream, e);
+                }
+                Inhibit(false)
+            });
+        }
+
         drawing_area.set_hexpand(true);
         drawing_area.set_vexpand(true);
         drawing_area.show();
@@ -302,37 +1282,140 @@ impl Widget for Ground {
     }
 }
 
+#[derive(Clone)]
 struct State {
     board_state: BoardState,
     drawable: Drawable,
     promotable: Promotable,
     pieces: Pieces,
+    clear_background: Option<(f64, f64, f64, f64)>,
+    scroll_navigates: bool,
+    view_only: bool,
+    padding: i32,
+    clock: Rc<dyn Clock>,
 }
 
 impl State {
     fn new() -> State {
+        let clock = Rc::new(SystemClock);
+
         State {
-            board_state: BoardState::new(),
+            board_state: BoardState::new(clock.now()),
             drawable: Drawable::new(),
             promotable: Promotable::new(),
-            pieces: Pieces::new(),
+            pieces: Pieces::new(clock.now()),
+            clear_background: None,
+            scroll_navigates: false,
+            view_only: false,
+            padding: 0,
+            clock,
+        }
+    }
+
+    /// A copy with all running animations finished, to render their final
+    /// frame without touching the board on screen.
+    fn settled(&self) -> State {
+        let mut state = self.clone();
+        state.board_state.settle();
+        state.pieces.settle();
+        state.promotable.settle();
+        state
+    }
+
+    /// Whether the board already shows exactly the given position and
+    /// hints.
+    fn shows(&self, pos: &Pos) -> bool {
+        self.pieces.shows(&pos.board) &&
+        self.board_state.check() == pos.check &&
+        self.board_state.last_move() == pos.last_move &&
+        self.board_state.last_move_extra() == pos.last_move_extra &&
+        self.board_state.turn() == pos.turn &&
+        *self.board_state.legals() == *pos.legals
+    }
+
+    fn interaction(&self) -> InteractionConfig {
+        InteractionConfig {
+            draw_shapes: self.drawable.enabled(),
+            erase_shapes_on_click: self.drawable.erase_on_click(),
+            shape_toggle: self.drawable.toggle(),
+            keep_selection_after_move: self.pieces.keep_selection_after_move(),
+            drag_threshold: self.pieces.drag_threshold(),
+            drag_delay: self.pieces.drag_delay(),
+            no_moves_cue: self.pieces.blocked_cue(),
+            scroll_navigates: self.scroll_navigates,
+            view_only: self.view_only,
+            movable_color: self.pieces.movable(),
+        }
+    }
+
+    fn set_interaction(&mut self, stream: &Stream, config: InteractionConfig) {
+        self.drawable.set_enabled(config.draw_shapes);
+        self.drawable.set_erase_on_click(config.erase_shapes_on_click);
+        self.drawable.set_toggle(config.shape_toggle);
+        self.pieces.set_keep_selection_after_move(config.keep_selection_after_move);
+        self.pieces.set_drag_threshold(config.drag_threshold);
+        self.pieces.set_drag_delay(config.drag_delay);
+        self.pieces.set_blocked_cue(config.no_moves_cue);
+        self.scroll_navigates = config.scroll_navigates;
+        self.pieces.set_movable(config.movable_color);
+        self.set_view_only(stream, config.view_only);
+    }
+
+    fn set_view_only(&mut self, stream: &Stream, view_only: bool) {
+        self.view_only = view_only;
+        if view_only {
+            // the button release would no longer be seen
+            if self.pieces.cancel_interaction() {
+                stream.emit(GroundMsg::SelectionChanged(None));
+            }
+            if self.drawable.cancel_drawing() {
+                stream.emit(GroundMsg::ShapeDrawing(None));
+            }
+            if self.promotable.cancel() {
+                stream.emit(GroundMsg::PromotionCancelled);
+            }
         }
     }
 
     fn queue_animation(&mut self, drawing_area: &DrawingArea) {
-        let ctx = WidgetContext::new(&self.board_state, drawing_area);
+        let ctx = WidgetContext::new(&self.board_state, drawing_area, self.padding, self.clock.now());
+        self.board_state.queue_animation(&ctx);
         self.pieces.queue_animation(&ctx);
         self.promotable.queue_animation(&ctx);
     }
 
     fn draw(&self, drawing_area: &DrawingArea, cr: &Context) -> Result<(), cairo::Error> {
-        let ctx = WidgetContext::new(&self.board_state, drawing_area);
+        self.draw_background(cr)?;
+        let ctx = WidgetContext::new(&self.board_state, drawing_area, self.padding, self.clock.now());
         cr.set_matrix(ctx.matrix());
+        self.draw_layers(cr, true)
+    }
 
-        // draw
-        self.board_state.draw(cr)?;
+    /// Render the board centered in a rectangle of the given size, without
+    /// a widget. The current transformation of the context is respected.
+    fn render(&self, cr: &Context, width: i32, height: i32) -> Result<(), cairo::Error> {
+        self.draw_background(cr)?;
+        cr.transform(self.board_state.matrix(0, 0, width, height));
+        self.draw_layers(cr, false)
+    }
+
+    fn draw_background(&self, cr: &Context) -> Result<(), cairo::Error> {
+        // fill the whole allocation, including the area around a
+        // letterboxed board
+        if let Some((r, g, b, a)) = self.clear_background {
+            cr.set_source_rgba(r, g, b, a);
+            cr.paint()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_layers(&self, cr: &Context, cached: bool) -> Result<(), cairo::Error> {
+        self.board_state.draw(cr, cached)?;
+        self.board_state.draw_material(cr, &self.pieces.visible_board())?;
+        self.drawable.draw_tints(cr)?;
         self.pieces.draw(cr, &self.board_state, &self.promotable)?;
-        self.drawable.draw(cr)?;
+        self.drawable.draw(cr, &self.board_state, self.pieces.occupied())?;
         self.pieces.draw_drag(cr, &self.board_state)?;
         self.promotable.draw(cr, &self.board_state)?;
 
@@ -340,20 +1423,66 @@ impl State {
     }
 
     fn button_release_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventButton) {
-        let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
+        if self.view_only {
+            return;
+        }
+
+        let ctx = EventContext::new(&self.board_state, stream, drawing_area, self.padding, e.position(), self.clock.now());
         self.pieces.drag_mouse_up(&ctx);
-        self.drawable.mouse_up(&ctx);
+        self.drawable.mouse_up(&ctx, e);
     }
 
     fn motion_notify_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventMotion) {
-        let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
+        if self.view_only {
+            return;
+        }
+
+        let ctx = EventContext::new(&self.board_state, stream, drawing_area, self.padding, e.position(), self.clock.now());
         self.promotable.mouse_move(&ctx);
         self.pieces.drag_mouse_move(&ctx);
+        self.pieces.hover_mouse_move(&ctx);
         self.drawable.mouse_move(&ctx);
     }
 
+    fn leave_notify_event(&mut self, drawing_area: &DrawingArea) {
+        let ctx = WidgetContext::new(&self.board_state, drawing_area, self.padding, self.clock.now());
+        self.pieces.hover_leave(&ctx);
+    }
+
+    fn scroll_event(&self, stream: &Stream, e: &EventScroll) -> Inhibit {
+        if !self.scroll_navigates {
+            return Inhibit(false);
+        }
+
+        match e.direction() {
+            ScrollDirection::Up => stream.emit(GroundMsg::NavigateBack),
+            ScrollDirection::Down => stream.emit(GroundMsg::NavigateForward),
+            _ => return Inhibit(false),
+        }
+
+        Inhibit(true)
+    }
+
     fn button_press_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventButton) {
-        let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
+        if self.view_only {
+            return;
+        }
+
+        let ctx = EventContext::new(&self.board_state, stream, drawing_area, self.padding, e.position(), self.clock.now());
+
+        // gtk sends an extra event after the second press of a double
+        // click. the presses themselves were already handled as clicks.
+        match e.event_type() {
+            EventType::DoubleButtonPress => {
+                if let (1, Some(square)) = (e.button(), ctx.square()) {
+                    stream.emit(GroundMsg::SquareDoubleClicked(square));
+                }
+                return;
+            }
+            EventType::TripleButtonPress => return,
+            _ => {}
+        }
+
         let promotable = &mut self.promotable;
         let pieces = &mut self.pieces;
 
@@ -368,23 +1497,16 @@ impl State {
 pub(crate) struct WidgetContext<'a> {
     matrix: Matrix,
     drawing_area: &'a DrawingArea,
+    now: SteadyTime,
 }
 
 impl<'a> WidgetContext<'a> {
-    fn new(board_state: &'a BoardState, drawing_area: &'a DrawingArea) -> WidgetContext<'a>
+    fn new(board_state: &BoardState, drawing_area: &'a DrawingArea, padding: i32, now: SteadyTime) -> WidgetContext<'a>
     {
         let alloc = drawing_area.allocation();
-        let size = max(min(alloc.width(), alloc.height()), 9);
-
-        let mut matrix = Matrix::identity();
-        matrix.translate(f64::from(alloc.x()), f64::from(alloc.y()));
-
-        matrix.translate(f64::from(alloc.width()) / 2.0, f64::from(alloc.height()) / 2.0);
-        matrix.scale(f64::from(size) / 9.0, f64::from(size) / 9.0);
-        matrix.rotate(board_state.orientation().fold_wb(0.0, PI));
-        matrix.translate(-4.0, -4.0);
-
-        WidgetContext { matrix, drawing_area }
+        let matrix = board_state.matrix(alloc.x() + padding, alloc.y() + padding,
+                                        alloc.width() - 2 * padding, alloc.height() - 2 * padding);
+        WidgetContext { matrix, drawing_area, now }
     }
 
     fn invert_pos(&self, (x, y): (f64, f64)) -> (f64, f64) {
@@ -393,10 +1515,25 @@ impl<'a> WidgetContext<'a> {
             .transform_point(x, y)
     }
 
+    fn widget_coords_to_pos(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let alloc = self.drawing_area.allocation();
+        self.invert_pos((x + f64::from(alloc.x()), y + f64::from(alloc.y())))
+    }
+
+    fn pos_to_widget_coords(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (x, y) = self.matrix().transform_point(x, y);
+        let alloc = self.drawing_area.allocation();
+        (x - f64::from(alloc.x()), y - f64::from(alloc.y()))
+    }
+
     pub fn matrix(&self) -> Matrix {
         self.matrix
     }
 
+    pub fn now(&self) -> SteadyTime {
+        self.now
+    }
+
     pub fn queue_draw(&self) {
         self.drawing_area.queue_draw()
     }
@@ -426,6 +1563,7 @@ impl<'a> WidgetContext<'a> {
 
 pub(crate) struct EventContext<'a> {
     widget: WidgetContext<'a>,
+    board_state: &'a BoardState,
     stream: &'a Stream,
     pos: (f64, f64),
     square: Option<Square>,
@@ -435,16 +1573,17 @@ impl<'a> EventContext<'a> {
     fn new(board_state: &'a BoardState,
            stream: &'a Stream,
            drawing_area: &'a DrawingArea,
-           pos: (f64, f64)) -> EventContext<'a>
+           padding: i32,
+           pos: (f64, f64),
+           now: SteadyTime) -> EventContext<'a>
     {
-        let widget = WidgetContext::new(board_state, drawing_area);
-        let alloc = drawing_area.allocation();
-        let pos = (pos.0 + f64::from(alloc.x()), pos.1 + f64::from(alloc.y()));
-        let pos = widget.invert_pos(pos);
+        let widget = WidgetContext::new(board_state, drawing_area, padding, now);
+        let pos = widget.widget_coords_to_pos(pos);
         let square = pos_to_square(pos);
 
         EventContext {
             widget,
+            board_state,
             stream,
             pos,
             square,
@@ -455,6 +1594,10 @@ impl<'a> EventContext<'a> {
         &self.widget
     }
 
+    pub fn board_state(&self) -> &'a BoardState {
+        self.board_state
+    }
+
     pub fn stream(&self) -> &'a Stream {
         self.stream
     }
@@ -466,4 +1609,105 @@ impl<'a> EventContext<'a> {
     pub fn square(&self) -> Option<Square> {
         self.square
     }
+
+    pub fn now(&self) -> SteadyTime {
+        self.widget.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pawn_move(from: Square, to: Square) -> Move {
+        Move::Normal { role: Role::Pawn, from, capture: None, to, promotion: None }
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        assert_eq!(Pos::new(&Chess::default()).validate(), Ok(()));
+        assert_eq!(Pos::from_board(Board::default()).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_check_without_king() {
+        let pos = Pos::new(&Chess::default()).with_check(Square::E4);
+        assert_eq!(pos.validate(), Err(PosError::CheckWithoutKing(Square::E4)));
+
+        let pos = Pos::new(&Chess::default()).with_check(Square::D1);
+        assert_eq!(pos.validate(), Err(PosError::CheckWithoutKing(Square::D1)));
+    }
+
+    #[test]
+    fn test_validate_check_on_wrong_side() {
+        let pos = Pos::new(&Chess::default()).with_check(Square::E8);
+        assert_eq!(pos.validate(), Err(PosError::CheckOnWrongSide(Square::E8)));
+    }
+
+    #[test]
+    fn test_validate_legal_move_from_empty_square() {
+        let mut legals = MoveList::new();
+        legals.push(pawn_move(Square::E3, Square::E4));
+        let pos = Pos::from_board(Board::default()).with_legals(legals);
+        assert_eq!(pos.validate(), Err(PosError::LegalMoveFromEmptySquare(pawn_move(Square::E3, Square::E4))));
+    }
+
+    #[test]
+    fn test_validate_legal_move_of_wrong_side() {
+        let mut legals = MoveList::new();
+        legals.push(pawn_move(Square::E7, Square::E5));
+        let pos = Pos::new(&Chess::default()).with_legals(legals);
+        assert_eq!(pos.validate(), Err(PosError::LegalMoveOfWrongSide(pawn_move(Square::E7, Square::E5))));
+    }
+
+    #[test]
+    fn test_from_squares() {
+        let mut squares = [None; 64];
+        for &sq in &Square::ALL {
+            squares[usize::from(sq)] = Board::default().piece_at(sq);
+        }
+        assert_eq!(Pos::from_squares(squares).board, Board::default());
+
+        // no hints are derived from the pieces, not even check
+        let mut squares = [None; 64];
+        squares[usize::from(Square::E1)] = Some(Color::White.king());
+        squares[usize::from(Square::E2)] = Some(Color::White.rook());
+        squares[usize::from(Square::E8)] = Some(Color::Black.king());
+        let pos = Pos::from_squares(squares);
+
+        let mut board = Board::empty();
+        board.set_piece_at(Square::E1, Color::White.king());
+        board.set_piece_at(Square::E2, Color::White.rook());
+        board.set_piece_at(Square::E8, Color::Black.king());
+        assert_eq!(pos.board, board);
+        assert!(pos.legals.is_empty());
+        assert_eq!(pos.check, None);
+        assert_eq!(pos.turn, None);
+        assert_eq!(pos.validate(), Ok(()));
+    }
+
+    fn last_move_extra(m: Move) -> Bitboard {
+        Pos::from_board(Board::empty()).with_last_move(&m).last_move_extra
+    }
+
+    #[test]
+    fn test_last_move_extra() {
+        // king side castling
+        assert_eq!(last_move_extra(Move::Castle { king: Square::E1, rook: Square::H1 }),
+                   Bitboard::from_square(Square::G1) | Bitboard::from_square(Square::F1));
+
+        // queen side castling
+        assert_eq!(last_move_extra(Move::Castle { king: Square::E8, rook: Square::A8 }),
+                   Bitboard::from_square(Square::C8) | Bitboard::from_square(Square::D8));
+
+        // chess960 castling, with the king moving away from the rook
+        assert_eq!(last_move_extra(Move::Castle { king: Square::B1, rook: Square::A1 }),
+                   Bitboard::from_square(Square::C1) | Bitboard::from_square(Square::D1));
+
+        // en passant marks the captured pawn
+        assert_eq!(last_move_extra(Move::EnPassant { from: Square::E5, to: Square::D6 }),
+                   Bitboard::from_square(Square::D5));
+
+        assert_eq!(last_move_extra(pawn_move(Square::E2, Square::E4)), Bitboard(0));
+    }
 }
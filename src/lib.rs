@@ -23,6 +23,7 @@
 extern crate gtk;
 extern crate gdk;
 extern crate cairo;
+#[cfg(feature = "svg-pieces")]
 extern crate rsvg;
 extern crate shakmaty;
 extern crate time;
@@ -30,14 +31,22 @@ extern crate relm;
 #[macro_use]
 extern crate relm_derive;
 
+#[cfg(not(any(feature = "svg-pieces", feature = "font-pieces")))]
+compile_error!("enable at least one of the features svg-pieces or font-pieces");
+
 mod ground;
 mod boardstate;
 mod pieceset;
 mod pieces;
 mod promotable;
 mod drawable;
+mod clock;
 mod util;
 
-pub use ground::{Ground, GroundMsg, Pos};
+pub use ground::{Ground, GroundMsg, InteractionConfig, Pos, PosError, SoundKind, render_pos};
 pub use GroundMsg::*;
-pub use drawable::{DrawBrush, DrawShape};
+pub use drawable::{DrawBrush, DrawShape, ParseDrawShapeError, ShapeToggle};
+pub use boardstate::{CoordinatesStyle, BoardTheme, FlipAnimation, MaterialBalance, render_board_thumbnail};
+pub use pieceset::{PieceSet, PieceImage};
+pub use pieces::{SelectionStyle, MoveHintStyle, DragGhostStyle, FigurineState};
+pub use clock::{Clock, SystemClock, ManualClock};
@@ -16,75 +16,182 @@
 
 use std::f64::consts::PI;
 
-use time::SteadyTime;
+use time::{Duration, SteadyTime};
 
 use gdk::EventButton;
-use cairo::Context;
-use rsvg::HandleExt;
+use cairo::{Context, Operator};
 
-use shakmaty::{Square, Piece, Bitboard, Board};
+use shakmaty::{Square, Color, Piece, Role, Rank, Bitboard, Board};
 
 use util::{ease, file_to_float, pos_to_square, rank_to_float, square_to_pos};
 use promotable::Promotable;
 use boardstate::BoardState;
 use ground::{GroundMsg, EventContext, WidgetContext};
 
+/// How to highlight the selected square.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SelectionStyle {
+    /// Fill the whole square.
+    Fill,
+    /// Outline the square, so that the piece stays fully visible.
+    Ring,
+}
+
+/// How to show the legal destinations of the selected piece.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum MoveHintStyle {
+    /// Dots on empty squares and corner triangles on captures.
+    Dots,
+    /// Softly pulsing borders around the destination squares.
+    Glow,
+}
+
+/// How to show a piece on its original square while it is dragged.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DragGhostStyle {
+    /// A faint ghost of the piece.
+    Faded,
+    /// Nothing.
+    Hidden,
+    /// The piece as usual.
+    Full,
+}
+
+/// Snapshot of the animation of a piece on the board.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FigurineState {
+    /// The square the piece is on, or is moving to.
+    pub square: Square,
+    /// The piece.
+    pub piece: Piece,
+    /// Progress of the current animation, from 0 to 1.
+    pub elapsed: f64,
+    /// Current position of the centre of the piece, in squares from the
+    /// top left corner of the board as seen from White.
+    pub pos: (f64, f64),
+    /// Whether the piece is fading out, because it was captured or
+    /// removed.
+    pub fading: bool,
+    /// Current opacity, from 0 to 1.
+    pub alpha: f64,
+}
+
+#[derive(Clone)]
 pub struct Pieces {
     figurines: Vec<Figurine>,
     selected: Option<Square>,
+    selection_style: SelectionStyle,
+    move_hint_style: MoveHintStyle,
+    glow: f64,
+    premove: Option<(Square, Square, Piece)>,
+    premove_color: (f64, f64, f64, f64),
+    blocked: Option<Blocked>,
+    blocked_cue: bool,
+    keep_selection_after_move: bool,
+    deselect_on_release: bool,
     drag: Option<Drag>,
+    drag_threshold: f64,
+    drag_ghost_style: DragGhostStyle,
+    drag_delay: Duration,
+    drop_snap: Option<f64>,
+    animation_duration: Option<f64>,
+    crossfade_threshold: Option<usize>,
+    flipped: Bitboard,
+    hidden: Bitboard,
+    coordinate_ruler: bool,
+    hovered: Option<Square>,
+    movable: Option<Color>,
+    max_fading: usize,
+    capture_tint: Option<(f64, f64, f64, f64)>,
+    drag_piece_override: Option<Piece>,
     past: SteadyTime,
 }
 
+#[derive(Clone)]
+struct Blocked {
+    square: Square,
+    since: SteadyTime,
+    elapsed: f64,
+}
+
+#[derive(Clone)]
 struct Drag {
     square: Square,
     piece: Piece,
     start: (f64, f64),
     pos: (f64, f64),
+    since: SteadyTime,
     threshold: bool,
 }
 
+#[derive(Clone)]
 pub struct Figurine {
     square: Square,
     piece: Piece,
     start: (f64, f64),
     elapsed: f64,
+    duration: f64,
     time: SteadyTime,
     last_drag: SteadyTime,
+    drop_pos: (f64, f64),
     fading: bool,
     replaced: bool,
+    appearing: bool,
     dragging: bool,
 }
 
 impl Pieces {
-    pub fn new() -> Pieces {
-        Pieces::new_from_board(&Board::new())
+    pub fn new(now: SteadyTime) -> Pieces {
+        Pieces::new_from_board(&Board::new(), now)
     }
 
-    pub fn new_from_board(board: &Board) -> Pieces {
-        let now = SteadyTime::now();
-
+    pub fn new_from_board(board: &Board, now: SteadyTime) -> Pieces {
         Pieces {
             selected: None,
+            selection_style: SelectionStyle::Fill,
+            move_hint_style: MoveHintStyle::Dots,
+            glow: 0.0,
+            premove: None,
+            premove_color: (0.08, 0.12, 0.33, 0.5),
+            blocked: None,
+            blocked_cue: false,
+            keep_selection_after_move: false,
+            deselect_on_release: false,
             drag: None,
+            drag_threshold: 4.0,
+            drag_ghost_style: DragGhostStyle::Faded,
+            drag_delay: Duration::zero(),
+            drop_snap: None,
+            animation_duration: Some(300.0),
+            crossfade_threshold: None,
+            flipped: Bitboard(0),
+            hidden: Bitboard(0),
+            coordinate_ruler: false,
+            hovered: None,
+            movable: None,
+            max_fading: 32,
+            capture_tint: None,
+            drag_piece_override: None,
             past: now,
             figurines: board.clone().into_iter().map(|(square, piece)| Figurine {
                 square,
                 piece,
                 start: (0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank())),
                 elapsed: 0.0,
+                duration: 300.0,
                 time: now,
                 last_drag: now,
+                drop_pos: (0.0, 0.0),
                 fading: false,
                 replaced: false,
+                appearing: false,
                 dragging: false,
             }).collect(),
         }
     }
 
-    pub fn set_board(&mut self, board: &Board) {
+    pub fn set_board(&mut self, board: &Board, last_move: Option<(Square, Square)>, now: SteadyTime) {
         // clean faded figurines
-        let now = SteadyTime::now();
         self.figurines.retain(|f| !f.fading || f.alpha() > 0.0001);
 
         // diff
@@ -92,6 +199,26 @@ impl Pieces {
             self.figurine_at(sq).map_or(true, |f| f.piece != piece)
         }).collect();
 
+        // cross-fade the whole board instead of sliding pieces around, if
+        // too many squares changed
+        let removed = self.figurines.iter().filter(|f| {
+            !f.fading && !board.by_piece(f.piece).contains(f.square)
+        }).count();
+        let crossfade = self.crossfade_threshold.map_or(false, |threshold| added.len() + removed > threshold);
+
+        // complete fades on squares that change again
+        let changed: Bitboard = added.iter().map(|&(sq, _)| sq).chain(self.figurines.iter().filter(|f| {
+            !f.fading && !board.by_piece(f.piece).contains(f.square)
+        }).map(|f| f.square)).collect();
+        self.figurines.retain(|f| !f.fading || !changed.contains(f.square));
+
+        // prefer the last move hint over the distance heuristic
+        let hint = last_move.filter(|&(orig, dest)| {
+            self.figurine_at(orig).map_or(false, |f| {
+                !board.by_piece(f.piece).contains(orig) && added.contains(&(dest, f.piece))
+            })
+        });
+
         for figurine in &mut self.figurines {
             if figurine.fading {
                 continue;
@@ -100,9 +227,16 @@ impl Pieces {
             // figurine was removed from the square
             if !board.by_piece(figurine.piece).contains(figurine.square) {
                 // checkpoint animation
-                figurine.start = figurine.pos();
-                figurine.elapsed = 0.0;
-                figurine.time = now;
+                match self.animation_duration {
+                    Some(duration) => {
+                        figurine.start = figurine.pos_at(figurine.progress(now));
+                        figurine.elapsed = 0.0;
+                        figurine.duration = duration;
+                        figurine.time = now;
+                    }
+                    None => figurine.elapsed = 1.0,
+                }
+                figurine.appearing = false;
 
                 // cancel drag
                 if figurine.dragging {
@@ -110,25 +244,36 @@ impl Pieces {
                     self.drag = None;
                 }
 
-                let best = added
-                    .iter()
-                    .filter(|&&(_, p)| p == figurine.piece)
-                    .min_by_key(|&&(sq, _)| figurine.square.distance(sq))
-                    .map(|&(sq, _)| sq);
+                let best = match hint {
+                    _ if crossfade => None,
+                    Some((orig, dest)) if orig == figurine.square => Some(dest),
+                    _ => added
+                        .iter()
+                        .filter(|&&(sq, p)| p == figurine.piece && hint.map_or(true, |(_, dest)| dest != sq))
+                        .min_by_key(|&&(sq, _)| figurine.square.distance(sq))
+                        .map(|&(sq, _)| sq),
+                };
 
                 if let Some(best) = best {
                     // found a close square it could have moved to
                     figurine.square = best;
                     added.retain(|&(sq, _)| sq != best);
 
-                    // snap dragged figurine to square
-                    if (now - figurine.last_drag).num_milliseconds() < 200 {
-                        figurine.start = square_to_pos(figurine.square);
+                    // snap dragged figurine to square, from where it was
+                    // dropped
+                    if self.animation_duration.is_some() && (now - figurine.last_drag).num_milliseconds() < 200 {
+                        match self.drop_snap {
+                            Some(duration) => {
+                                figurine.start = figurine.drop_pos;
+                                figurine.duration = duration;
+                            }
+                            None => figurine.start = square_to_pos(figurine.square),
+                        }
                     }
                 } else {
                     // fade it out
                     figurine.fading = true;
-                    figurine.replaced = board.occupied().contains(figurine.square);
+                    figurine.replaced = !crossfade && board.occupied().contains(figurine.square);
                 }
             }
         }
@@ -139,20 +284,221 @@ impl Pieces {
                 square,
                 piece,
                 start: (0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank())),
-                elapsed: 0.0,
+                elapsed: if self.animation_duration.is_some() { 0.0 } else { 1.0 },
+                duration: self.animation_duration.unwrap_or(300.0),
                 time: now,
                 last_drag: self.past,
+                drop_pos: (0.0, 0.0),
                 fading: false,
                 replaced: false,
+                appearing: crossfade,
                 dragging: false,
             });
         }
+
+        // drop the oldest fading figurines, so that they do not pile up
+        // when boards are set faster than they fade out
+        while self.figurines.iter().filter(|f| f.fading).count() > self.max_fading {
+            let oldest = self.figurines.iter()
+                .enumerate()
+                .filter(|&(_, f)| f.fading)
+                .min_by_key(|&(_, f)| f.time)
+                .map(|(i, _)| i);
+
+            match oldest {
+                Some(i) => { self.figurines.remove(i); },
+                None => break,
+            }
+        }
+    }
+
+    pub fn settle(&mut self) {
+        self.figurines.retain(|f| !f.fading);
+        for figurine in &mut self.figurines {
+            figurine.elapsed = 1.0;
+            figurine.appearing = false;
+        }
+        self.blocked = None;
+    }
+
+    pub fn set_selection_style(&mut self, style: SelectionStyle) {
+        self.selection_style = style;
+    }
+
+    pub fn set_move_hint_style(&mut self, style: MoveHintStyle) {
+        self.move_hint_style = style;
+    }
+
+    pub fn set_keep_selection_after_move(&mut self, keep: bool) {
+        self.keep_selection_after_move = keep;
+    }
+
+    pub fn keep_selection_after_move(&self) -> bool {
+        self.keep_selection_after_move
+    }
+
+    pub fn set_drag_threshold(&mut self, threshold: f64) {
+        self.drag_threshold = threshold;
+    }
+
+    pub fn drag_threshold(&self) -> f64 {
+        self.drag_threshold
+    }
+
+    pub fn set_drag_ghost_style(&mut self, style: DragGhostStyle) {
+        self.drag_ghost_style = style;
+    }
+
+    pub fn set_drag_delay(&mut self, delay: Duration) {
+        self.drag_delay = delay;
+    }
+
+    pub fn drag_delay(&self) -> Duration {
+        self.drag_delay
+    }
+
+    pub fn set_animation_duration(&mut self, duration: Option<Duration>) {
+        self.animation_duration = duration.map(|d| d.num_milliseconds() as f64);
+    }
+
+    pub fn animation_duration(&self) -> Option<f64> {
+        self.animation_duration
+    }
+
+    pub fn set_drop_snap(&mut self, duration: Option<Duration>) {
+        self.drop_snap = duration.map(|d| d.num_milliseconds() as f64);
+    }
+
+    pub fn set_blocked_cue(&mut self, blocked_cue: bool) {
+        self.blocked_cue = blocked_cue;
+    }
+
+    pub fn blocked_cue(&self) -> bool {
+        self.blocked_cue
+    }
+
+    pub fn set_crossfade_threshold(&mut self, threshold: Option<usize>) {
+        self.crossfade_threshold = threshold;
+    }
+
+    pub fn set_max_fading(&mut self, max_fading: usize) {
+        self.max_fading = max_fading;
+    }
+
+    pub fn set_drag_piece_override(&mut self, piece: Option<Piece>) {
+        self.drag_piece_override = piece;
+    }
+
+    pub fn set_capture_tint(&mut self, tint: Option<(f64, f64, f64, f64)>) {
+        self.capture_tint = tint;
+    }
+
+    pub fn set_coordinate_ruler(&mut self, coordinate_ruler: bool) {
+        self.coordinate_ruler = coordinate_ruler;
+    }
+
+    pub fn set_movable(&mut self, movable: Option<Color>) {
+        self.movable = movable;
+    }
+
+    pub fn movable(&self) -> Option<Color> {
+        self.movable
+    }
+
+    /// Drop the selection, the hovered square and any drag in progress,
+    /// returning whether a square was selected.
+    pub fn cancel_interaction(&mut self) -> bool {
+        self.drag = None;
+        for figurine in &mut self.figurines {
+            figurine.dragging = false;
+        }
+        self.deselect_on_release = false;
+        self.hovered = None;
+        self.selected.take().is_some()
+    }
+
+    fn is_movable(&self, square: Square) -> bool {
+        // selecting a hidden piece would give it away
+        !self.hidden.contains(square) &&
+        self.figurine_at(square).map_or(false, |f| self.movable.map_or(true, |color| f.piece.color == color))
+    }
+
+    pub fn set_hidden(&mut self, hidden: Bitboard) {
+        self.hidden = hidden;
+    }
+
+    pub fn set_flipped(&mut self, flipped: Bitboard) {
+        self.flipped = flipped;
     }
 
     pub fn occupied(&self) -> Bitboard {
         self.figurines.iter().filter(|f| !f.fading).map(|f| f.square).collect()
     }
 
+    pub fn occupied_by(&self, color: Color) -> Bitboard {
+        self.figurines.iter().filter(|f| !f.fading && f.piece.color == color).map(|f| f.square).collect()
+    }
+
+    /// The board with the shown pieces, ignoring those that are fading
+    /// out.
+    pub fn board(&self) -> Board {
+        let mut board = Board::empty();
+        for figurine in self.figurines.iter().filter(|f| !f.fading) {
+            board.set_piece_at(figurine.square, figurine.piece);
+        }
+        board
+    }
+
+    /// Like `board()`, but without the hidden pieces.
+    pub fn visible_board(&self) -> Board {
+        let mut board = self.board();
+        for square in self.hidden {
+            board.discard_piece_at(square);
+        }
+        board
+    }
+
+    /// Whether exactly the pieces of the board are shown, ignoring those
+    /// that are fading out.
+    pub fn shows(&self, board: &Board) -> bool {
+        let mut figurines = self.figurines.iter().filter(|f| !f.fading);
+        figurines.clone().count() == board.occupied().count() &&
+        figurines.all(|f| board.piece_at(f.square) == Some(f.piece))
+    }
+
+    /// Squares where the board has a piece that is not shown, where a
+    /// shown piece is missing on the board, and where the pieces differ.
+    pub fn diff(&self, board: &Board) -> (Bitboard, Bitboard, Bitboard) {
+        let mut added = Bitboard(0);
+        let mut removed = Bitboard(0);
+        let mut changed = Bitboard(0);
+
+        for square in Square::ALL {
+            match (self.figurine_at(square).map(|f| f.piece), board.piece_at(square)) {
+                (None, Some(_)) => added.add(square),
+                (Some(_), None) => removed.add(square),
+                (Some(shown), Some(piece)) if shown != piece => changed.add(square),
+                _ => {}
+            }
+        }
+
+        (added, removed, changed)
+    }
+
+    pub fn figurine_states(&self, now: SteadyTime) -> Vec<FigurineState> {
+        self.figurines.iter().map(|f| {
+            let elapsed = f.progress(now);
+            FigurineState {
+                square: f.square,
+                piece: f.piece,
+                elapsed,
+                pos: f.pos_at(elapsed),
+                fading: f.fading,
+                alpha: f.alpha_at(elapsed),
+            }
+        }).collect()
+    }
+
     pub fn figurine_at(&self, square: Square) -> Option<&Figurine> {
         self.figurines.iter().find(|f| !f.fading && f.square == square)
     }
@@ -165,27 +511,99 @@ impl Pieces {
         self.figurines.iter_mut().find(|f| f.dragging)
     }
 
+    /// The queued premove, with the piece that was premoved.
+    pub fn premove(&self) -> Option<(Square, Square, Piece)> {
+        self.premove
+    }
+
+    pub fn set_premove(&mut self, premove: Option<(Square, Square, Piece)>) {
+        self.premove = premove;
+    }
+
+    pub fn clear_premove(&mut self) {
+        self.premove = None;
+    }
+
+    pub fn set_premove_color(&mut self, color: (f64, f64, f64, f64)) {
+        self.premove_color = color;
+    }
+
+    fn user_move(&mut self, ctx: &EventContext, orig: Square, dest: Square) -> bool {
+        // moving a piece of the side that is not to move queues a premove
+        let premove = match (ctx.board_state().turn(), self.figurine_at(orig)) {
+            (Some(turn), Some(figurine)) if figurine.piece.color != turn => Some(figurine.piece),
+            _ => None,
+        };
+
+        if let Some(piece) = premove {
+            self.premove = Some((orig, dest, piece));
+            ctx.stream().emit(GroundMsg::PremoveSet(orig, dest));
+        } else {
+            ctx.stream().emit(GroundMsg::UserMove(orig, dest, None));
+        }
+
+        premove.is_some()
+    }
+
+    fn cancel_premove(&mut self, ctx: &EventContext) {
+        if self.premove.take().is_some() {
+            ctx.stream().emit(GroundMsg::PremoveCancelled);
+        }
+    }
+
     pub(crate) fn selection_mouse_down(&mut self, ctx: &EventContext, e: &EventButton) {
         let orig = self.selected.take();
 
         if e.button() == 1 {
             let dest = ctx.square();
-            self.selected = dest.filter(|sq| self.occupied().contains(*sq));
+            self.selected = dest.filter(|sq| self.is_movable(*sq));
+            self.deselect_on_release = false;
+
+            let mut premoved = false;
 
             if let (Some(orig), Some(dest)) = (orig, dest) {
                 self.selected = None;
                 if orig != dest {
-                    ctx.stream().emit(GroundMsg::UserMove(orig, dest, None));
+                    premoved = self.user_move(ctx, orig, dest);
+                    if self.keep_selection_after_move {
+                        self.selected = Some(dest);
+                    }
+                } else {
+                    // tapping the selected piece again deselects it on
+                    // release, unless it is dragged away
+                    self.selected = Some(orig);
+                    self.deselect_on_release = true;
+                }
+            }
+
+            // any other click cancels a queued premove
+            if !premoved {
+                self.cancel_premove(ctx);
+            }
+
+            // flash pieces that can not move
+            if let (None, Some(selected)) = (orig, self.selected) {
+                let state = ctx.board_state();
+                if self.blocked_cue && !state.legals().is_empty() && state.move_targets(selected).is_empty() {
+                    self.blocked = Some(Blocked {
+                        square: selected,
+                        since: ctx.now(),
+                        elapsed: 0.0,
+                    });
                 }
             }
         }
 
+        if self.selected != orig {
+            ctx.stream().emit(GroundMsg::SelectionChanged(self.selected));
+        }
+
         ctx.widget().queue_draw();
     }
 
     pub(crate) fn drag_mouse_down(&mut self, ctx: &EventContext, e: &EventButton) {
         if e.button() == 1 {
-            if let Some(square) = ctx.square() {
+            if let Some(square) = ctx.square().filter(|sq| self.is_movable(*sq)) {
                 let piece = if let Some(figurine) = self.figurine_at_mut(square) {
                     figurine.dragging = true;
                     figurine.piece
@@ -198,6 +616,7 @@ impl Pieces {
                     piece,
                     start: ctx.pos(),
                     pos: ctx.pos(),
+                    since: ctx.now(),
                     threshold: false,
                 });
             }
@@ -216,14 +635,19 @@ impl Pieces {
                 ctx.widget().queue_draw_square(sq);
             }
 
+            // measure in pixels, so that small jitter of the pointer is
+            // treated as a click regardless of the board size. also wait
+            // for the delay, so that the ghost does not flash on a
+            // jittery click.
             let (dx, dy) = (drag.start.0 - drag.pos.0, drag.start.1 - drag.pos.1);
             let (pdx, pdy) = ctx.widget().matrix().transform_distance(dx, dy);
-            drag.threshold |= dx.hypot(dy) >= 0.1 || pdx.hypot(pdy) >= 4.0;
+            drag.threshold |= pdx.hypot(pdy) >= self.drag_threshold && ctx.now() - drag.since >= self.drag_delay;
 
             if drag.threshold {
                 // ensure orig square is selected
                 if self.selected != Some(drag.square) {
                   self.selected = Some(drag.square);
+                  ctx.stream().emit(GroundMsg::SelectionChanged(self.selected));
                   ctx.widget().queue_draw();
                 } else {
                   ctx.widget().queue_draw_square(drag.square);
@@ -232,12 +656,34 @@ impl Pieces {
         }
     }
 
+    pub(crate) fn hover_mouse_move(&mut self, ctx: &EventContext) {
+        let square = ctx.square();
+        if self.hovered != square {
+            if self.coordinate_ruler {
+                ctx.widget().queue_draw();
+            } else if self.selected.is_some() {
+                // only the destination hint under the pointer moves
+                for &sq in self.hovered.iter().chain(square.iter()) {
+                    ctx.widget().queue_draw_square(sq);
+                }
+            }
+            self.hovered = square;
+        }
+    }
+
+    pub(crate) fn hover_leave(&mut self, ctx: &WidgetContext) {
+        if self.hovered.take().is_some() {
+            ctx.queue_draw();
+        }
+    }
+
     pub(crate) fn drag_mouse_up(&mut self, ctx: &EventContext) {
         let (orig, dest) = if let Some(drag) = self.drag.take() {
             ctx.widget().queue_draw();
 
             if let Some(ref mut figurine) = self.dragging_mut() {
-                figurine.last_drag = SteadyTime::now();
+                figurine.last_drag = ctx.now();
+                figurine.drop_pos = ctx.pos();
                 figurine.dragging = false;
             }
 
@@ -246,16 +692,26 @@ impl Pieces {
             if drag.square != dest {
                 (drag.square, dest)
             } else {
+                if self.deselect_on_release && !drag.threshold && self.selected.take().is_some() {
+                    ctx.stream().emit(GroundMsg::SelectionChanged(None));
+                }
                 return;
             }
         } else {
             return;
         };
 
-        self.selected = None;
+        let selected = self.selected.take();
 
         if orig != dest {
-            ctx.stream().emit(GroundMsg::UserMove(orig, dest, None));
+            self.user_move(ctx, orig, dest);
+            if self.keep_selection_after_move {
+                self.selected = Some(dest);
+            }
+        }
+
+        if self.selected != selected {
+            ctx.stream().emit(GroundMsg::SelectionChanged(self.selected));
         }
     }
 
@@ -263,9 +719,26 @@ impl Pieces {
         for figurine in &mut self.figurines {
             figurine.queue_animation(ctx);
         }
+
+        if let Some(ref mut blocked) = self.blocked {
+            ctx.queue_draw_square(blocked.square);
+            blocked.elapsed = ((ctx.now() - blocked.since).num_milliseconds() as f64 / 500.0).min(1.0);
+        }
+
+        if self.blocked.as_ref().map_or(false, |b| b.elapsed >= 1.0) {
+            self.blocked = None;
+        }
+
+        // breathe while there are glowing move hints
+        if self.move_hint_style == MoveHintStyle::Glow && self.selected.is_some() {
+            let t = (ctx.now() - self.past).num_milliseconds() as f64 / 1500.0;
+            self.glow = 0.5 - 0.5 * (2.0 * PI * t).cos();
+            ctx.queue_draw();
+        }
     }
 
     pub(crate) fn draw(&self, cr: &Context, state: &BoardState, promotable: &Promotable) -> Result<(), cairo::Error> {
+        self.draw_ruler(cr, state)?;
         self.draw_selection(cr, state)?;
         self.draw_move_hints(cr, state)?;
 
@@ -296,6 +769,11 @@ impl Pieces {
             return Ok(());
         }
 
+        // hidden pieces still exist, but are not shown
+        if !figurine.fading && self.hidden.contains(figurine.square) {
+            return Ok(());
+        }
+
         // draw ghost when dragging
         let dragging =
             figurine.dragging &&
@@ -305,29 +783,90 @@ impl Pieces {
 
         let (x, y) = figurine.pos();
         cr.translate(x, y);
-        cr.rotate(state.orientation().fold_wb(0.0, PI));
+        cr.rotate(state.upright());
+        if self.flipped.contains(figurine.square) {
+            cr.rotate(PI);
+        }
         cr.translate(-0.5, -0.5);
-        cr.scale(state.piece_set().scale(), state.piece_set().scale());
+        state.draw_piece(cr, &figurine.piece)?;
 
-        state.piece_set().by_piece(&figurine.piece).render_cairo(cr);
+        if let Some((r, g, b, a)) = self.capture_tint {
+            if figurine.fading && figurine.replaced {
+                // color captured pieces more and more as they fade out
+                cr.set_operator(Operator::Atop);
+                cr.set_source_rgba(r, g, b, ease(0.0, a, figurine.elapsed));
+                cr.paint()?;
+            }
+        }
 
         cr.pop_group_to_source()?;
 
-        cr.paint_with_alpha(if dragging { 0.2 } else { figurine.alpha() })?;
+        let alpha = match self.drag_ghost_style {
+            DragGhostStyle::Faded if dragging => 0.2,
+            DragGhostStyle::Hidden if dragging => 0.0,
+            _ => figurine.alpha(),
+        };
+
+        cr.paint_with_alpha(alpha)?;
+
+        Ok(())
+    }
+
+    fn draw_ruler(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
+        // faintly tint the file and rank of the hovered square
+        if let Some(hovered) = self.hovered.filter(|_| self.coordinate_ruler) {
+            let (r, g, b, _) = state.theme().selected;
+            cr.set_source_rgba(r, g, b, 0.12);
+            cr.rectangle(file_to_float(hovered.file()), 0.0, 1.0, 8.0);
+            cr.rectangle(0.0, 7.0 - rank_to_float(hovered.rank()), 8.0, 1.0);
+            cr.fill()?;
+        }
 
         Ok(())
     }
 
     fn draw_selection(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
-        if let Some(selected) = self.selected {
-            cr.rectangle(file_to_float(selected.file()), 7.0 - rank_to_float(selected.rank()), 1.0, 1.0);
-            cr.set_source_rgba(0.08, 0.47, 0.11, 0.5);
+        if let Some((orig, dest, _)) = self.premove {
+            let (r, g, b, a) = self.premove_color;
+            cr.set_source_rgba(r, g, b, a);
+            for square in &[orig, dest] {
+                cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+                cr.fill()?;
+            }
+        }
+
+        if let Some(ref blocked) = self.blocked {
+            cr.rectangle(file_to_float(blocked.square.file()), 7.0 - rank_to_float(blocked.square.rank()), 1.0, 1.0);
+            cr.set_source_rgba(0.8, 0.1, 0.1, ease(0.6, 0.0, blocked.elapsed));
             cr.fill()?;
+        }
+
+        if let Some(selected) = self.selected {
+            let (r, g, b, a) = state.theme().selected;
+
+            cr.set_source_rgba(r, g, b, a);
+            match self.selection_style {
+                SelectionStyle::Fill => {
+                    cr.rectangle(file_to_float(selected.file()), 7.0 - rank_to_float(selected.rank()), 1.0, 1.0);
+                    cr.fill()?;
+                }
+                SelectionStyle::Ring => {
+                    let stroke = 0.08;
+                    cr.set_line_width(stroke);
+                    cr.rectangle(file_to_float(selected.file()) + 0.5 * stroke,
+                                 7.0 - rank_to_float(selected.rank()) + 0.5 * stroke,
+                                 1.0 - stroke, 1.0 - stroke);
+                    cr.stroke()?;
+                }
+            }
 
-            if let Some(hovered) = self.drag.as_ref().and_then(|d| pos_to_square(d.pos)) {
+            // the square under the dragged piece, or under the pointer
+            // when moving by clicks
+            let hovered = self.drag.as_ref().map_or(self.hovered, |d| pos_to_square(d.pos));
+            if let Some(hovered) = hovered {
                 if state.valid_move(selected, hovered) {
                     cr.rectangle(file_to_float(hovered.file()), 7.0 - rank_to_float(hovered.rank()), 1.0, 1.0);
-                    cr.set_source_rgba(0.08, 0.47, 0.11, 0.25);
+                    cr.set_source_rgba(r, g, b, 0.5 * a);
                     cr.fill()?;
                 }
             }
@@ -338,7 +877,25 @@ impl Pieces {
 
     fn draw_move_hints(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
         if let Some(selected) = self.selected {
-            cr.set_source_rgba(0.08, 0.47, 0.11, 0.5);
+            let (r, g, b, a) = state.theme().move_hint;
+
+            if self.move_hint_style == MoveHintStyle::Glow {
+                for square in state.move_targets(selected) {
+                    let (x, y) = (file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+
+                    // a wide faint halo with a narrower, stronger core
+                    for &(width, alpha) in &[(0.2, 0.4), (0.08, 1.0)] {
+                        cr.set_source_rgba(r, g, b, a * alpha * (0.3 + 0.7 * self.glow));
+                        cr.set_line_width(width);
+                        cr.rectangle(x + 0.5 * width, y + 0.5 * width, 1.0 - width, 1.0 - width);
+                        cr.stroke()?;
+                    }
+                }
+
+                return Ok(());
+            }
+
+            cr.set_source_rgba(r, g, b, a);
 
             let radius = 0.12;
             let corner = 1.8 * radius;
@@ -385,10 +942,22 @@ impl Pieces {
             Some(ref drag) if drag.threshold => {
                 cr.push_group();
                 cr.translate(drag.pos.0, drag.pos.1);
-                cr.rotate(state.orientation().fold_wb(0.0, PI));
+                cr.rotate(state.upright());
                 cr.translate(-0.5, -0.5);
-                cr.scale(state.piece_set().scale(), state.piece_set().scale());
-                state.piece_set().by_piece(&drag.piece).render_cairo(cr);
+                state.draw_piece(cr, self.drag_piece_override.as_ref().unwrap_or(&drag.piece))?;
+
+                // preview the promotion when a pawn hovers the last rank
+                let promoting = drag.piece.role == Role::Pawn && pos_to_square(drag.pos).map_or(false, |hovered| {
+                    hovered.rank() == drag.piece.color.fold_wb(Rank::Eighth, Rank::First) &&
+                    state.valid_move(drag.square, hovered)
+                });
+
+                if promoting {
+                    cr.translate(0.55, 0.0);
+                    cr.scale(0.45, 0.45);
+                    state.draw_piece(cr, &Piece { color: drag.piece.color, role: Role::Queen })?;
+                }
+
                 cr.pop_group_to_source()?;
                 cr.paint()?;
             }
@@ -404,41 +973,112 @@ impl Figurine {
         &self.piece
     }
 
-    pub fn set_pos(&mut self, pos: (f64, f64)) {
-        self.start = pos;
-        self.time = SteadyTime::now();
-        self.elapsed = 0.0;
+    pub fn set_pos(&mut self, pos: (f64, f64), now: SteadyTime, duration: Option<f64>) {
+        self.appearing = false;
+        if let Some(duration) = duration {
+            self.start = pos;
+            self.time = now;
+            self.elapsed = 0.0;
+            self.duration = duration;
+        }
     }
 
     fn pos(&self) -> (f64, f64) {
+        self.pos_at(self.elapsed)
+    }
+
+    fn pos_at(&self, elapsed: f64) -> (f64, f64) {
         if self.fading {
             self.start
         } else {
             let end = square_to_pos(self.square);
-            (ease(self.start.0, end.0, self.elapsed), ease(self.start.1, end.1, self.elapsed))
+            (ease(self.start.0, end.0, elapsed), ease(self.start.1, end.1, elapsed))
         }
     }
 
     fn alpha(&self) -> f64 {
+        self.alpha_at(self.elapsed)
+    }
+
+    fn alpha_at(&self, elapsed: f64) -> f64 {
         if self.replaced {
-            ease(0.5, 0.0, self.elapsed)
+            ease(0.5, 0.0, elapsed)
         } else if self.fading {
-            ease(1.0, 0.0, self.elapsed)
+            ease(1.0, 0.0, elapsed)
+        } else if self.appearing {
+            ease(0.0, 1.0, elapsed)
         } else {
             1.0
         }
     }
 
+    /// Progress of the animation at the given time, independent of when
+    /// the figurine was last drawn.
+    fn progress(&self, now: SteadyTime) -> f64 {
+        if self.elapsed >= 1.0 {
+            1.0
+        } else {
+            ((now - self.time).num_milliseconds() as f64 / self.duration.max(1.0)).min(1.0)
+        }
+    }
+
     fn queue_animation(&mut self, ctx: &WidgetContext) {
         if self.elapsed < 1.0 {
             let pos = self.pos();
             ctx.queue_draw_rect(pos.0 - 0.5, pos.1 - 0.5, 1.0, 1.0);
 
-            let now = SteadyTime::now();
-            self.elapsed = ((now - self.time).num_milliseconds() as f64 / 300.0).min(1.0);
+            self.elapsed = self.progress(ctx.now());
 
             let pos = self.pos();
             ctx.queue_draw_rect(pos.0 - 0.5, pos.1 - 0.5, 1.0, 1.0);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, ManualClock};
+
+    #[test]
+    fn test_animation_follows_clock() {
+        let clock = ManualClock::new();
+        let mut pieces = Pieces::new_from_board(&Board::default(), clock.now());
+
+        let mut board = Board::default();
+        let pawn = board.remove_piece_at(Square::E2).expect("pawn on e2");
+        board.set_piece_at(Square::E4, pawn);
+        pieces.set_board(&board, Some((Square::E2, Square::E4)), clock.now());
+
+        let state = |pieces: &Pieces, now| {
+            pieces.figurine_states(now).into_iter().find(|f| f.square == Square::E4).expect("pawn on e4")
+        };
+
+        let start = state(&pieces, clock.now());
+        assert_eq!(start.elapsed, 0.0);
+        assert_eq!(start.pos, square_to_pos(Square::E2));
+
+        clock.advance(Duration::milliseconds(150));
+        let middle = state(&pieces, clock.now());
+        assert_eq!(middle.elapsed, 0.5);
+        assert!(middle.pos.1 < square_to_pos(Square::E2).1);
+        assert!(middle.pos.1 > square_to_pos(Square::E4).1);
+
+        clock.advance(Duration::milliseconds(300));
+        let end = state(&pieces, clock.now());
+        assert_eq!(end.elapsed, 1.0);
+        assert_eq!(end.pos, square_to_pos(Square::E4));
+    }
+
+    #[test]
+    fn test_hidden_pieces() {
+        let mut pieces = Pieces::new_from_board(&Board::default(), ManualClock::new().now());
+        pieces.set_hidden(Bitboard::from_square(Square::E2));
+
+        assert!(!pieces.is_movable(Square::E2));
+        assert!(pieces.is_movable(Square::D2));
+
+        assert_eq!(pieces.board().piece_at(Square::E2), Some(Color::White.pawn()));
+        assert_eq!(pieces.visible_board().piece_at(Square::E2), None);
+    }
+}
@@ -14,21 +14,42 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use rsvg::Handle;
+#[cfg(feature = "svg-pieces")]
+use std::fs;
+#[cfg(feature = "svg-pieces")]
+use std::path::Path;
+
+use cairo::Context;
+#[cfg(feature = "font-pieces")]
+use cairo::{FontSlant, FontWeight};
+#[cfg(feature = "svg-pieces")]
+use rsvg::{Handle, HandleExt};
 
 use shakmaty::{Color, Role, Piece};
 
+/// The image of a single piece.
+#[derive(Debug, Clone)]
+pub enum PieceImage {
+    /// An SVG image.
+    #[cfg(feature = "svg-pieces")]
+    Svg(Handle),
+    /// A glyph of the font of the piece set.
+    #[cfg(feature = "font-pieces")]
+    Glyph(char),
+}
+
+#[derive(Debug, Clone)]
 struct PieceSetSide {
-    pawn: Handle,
-    knight: Handle,
-    bishop: Handle,
-    rook: Handle,
-    queen: Handle,
-    king: Handle,
+    pawn: PieceImage,
+    knight: PieceImage,
+    bishop: PieceImage,
+    rook: PieceImage,
+    queen: PieceImage,
+    king: PieceImage,
 }
 
 impl PieceSetSide {
-    fn by_role(&self, role: Role) -> &Handle {
+    fn by_role(&self, role: Role) -> &PieceImage {
         match role {
             Role::Pawn => &self.pawn,
             Role::Knight => &self.knight,
@@ -40,9 +61,14 @@ impl PieceSetSide {
     }
 }
 
+/// Images of the pieces.
+#[derive(Debug, Clone)]
 pub struct PieceSet {
     black: PieceSetSide,
     white: PieceSetSide,
+    scale: f64,
+    #[cfg(feature = "font-pieces")]
+    font: Option<String>,
 }
 
 impl PieceSet {
@@ -50,34 +76,203 @@ impl PieceSet {
         color.fold_wb(&self.white, &self.black)
     }
 
-    pub fn by_piece(&self, piece: &Piece) -> &Handle {
+    pub fn by_piece(&self, piece: &Piece) -> &PieceImage {
         self.by_color(piece.color).by_role(piece.role)
     }
 
+    /// Factor to scale the images to the unit square. For font based
+    /// sets this is the font size.
     pub fn scale(&self) -> f64 {
-        1.0 / 177.0
+        self.scale
+    }
+
+    /// Render a piece into the unit square at the origin.
+    pub(crate) fn render(&self, cr: &Context, piece: &Piece) -> Result<(), cairo::Error> {
+        match *self.by_piece(piece) {
+            #[cfg(feature = "svg-pieces")]
+            PieceImage::Svg(ref handle) => {
+                cr.save()?;
+                cr.scale(self.scale, self.scale);
+                handle.render_cairo(cr);
+                cr.restore()?;
+            }
+            #[cfg(feature = "font-pieces")]
+            PieceImage::Glyph(glyph) => {
+                let text = glyph.to_string();
+
+                cr.save()?;
+                cr.select_font_face(self.font.as_ref().map_or("serif", |f| f.as_str()), FontSlant::Normal, FontWeight::Normal);
+                cr.set_font_size(self.scale);
+                let e = cr.text_extents(&text)?;
+                cr.move_to(0.5 - 0.5 * e.width - e.x_bearing, 0.5 - 0.5 * e.height - e.y_bearing);
+                cr.text_path(&text);
+
+                // solid glyphs for both sides, filled with the piece color
+                let fill = piece.color.fold_wb(1.0, 0.0);
+                cr.set_source_rgb(fill, fill, fill);
+                cr.fill_preserve()?;
+                cr.set_source_rgb(0.0, 0.0, 0.0);
+                cr.set_line_width(0.02);
+                cr.stroke()?;
+                cr.restore()?;
+            }
+        }
+
+        Ok(())
     }
 }
 
+impl Default for PieceSet {
+    /// The merida piece set.
+    #[cfg(feature = "svg-pieces")]
+    fn default() -> PieceSet {
+        PieceSet::merida()
+    }
+
+    /// Unicode glyphs of a serif font, because the crate was built
+    /// without SVG support.
+    #[cfg(not(feature = "svg-pieces"))]
+    fn default() -> PieceSet {
+        PieceSet::from_font("serif", 0.9)
+    }
+}
+
+#[cfg(feature = "svg-pieces")]
+fn svg(data: &[u8], name: &str) -> PieceImage {
+    PieceImage::Svg(Handle::from_data(data).expect(name))
+}
+
+#[cfg(feature = "svg-pieces")]
 impl PieceSet {
+    /// The built-in merida piece set.
     pub fn merida() -> PieceSet {
         PieceSet {
             black: PieceSetSide {
-                pawn: Handle::from_data(include_bytes!("merida/bP.svg")).expect("merida/bP.svg"),
-                knight: Handle::from_data(include_bytes!("merida/bN.svg")).expect("merida/bN.svg"),
-                bishop: Handle::from_data(include_bytes!("merida/bB.svg")).expect("merida/bB.svg"),
-                rook: Handle::from_data(include_bytes!("merida/bR.svg")).expect("merida/bR.svg"),
-                queen: Handle::from_data(include_bytes!("merida/bQ.svg")).expect("merida/bQ.svg"),
-                king: Handle::from_data(include_bytes!("merida/bK.svg")).expect("merida/bK.svg"),
+                pawn: svg(include_bytes!("merida/bP.svg"), "merida/bP.svg"),
+                knight: svg(include_bytes!("merida/bN.svg"), "merida/bN.svg"),
+                bishop: svg(include_bytes!("merida/bB.svg"), "merida/bB.svg"),
+                rook: svg(include_bytes!("merida/bR.svg"), "merida/bR.svg"),
+                queen: svg(include_bytes!("merida/bQ.svg"), "merida/bQ.svg"),
+                king: svg(include_bytes!("merida/bK.svg"), "merida/bK.svg"),
+            },
+            white: PieceSetSide {
+                pawn: svg(include_bytes!("merida/wP.svg"), "merida/wP.svg"),
+                knight: svg(include_bytes!("merida/wN.svg"), "merida/wN.svg"),
+                bishop: svg(include_bytes!("merida/wB.svg"), "merida/wB.svg"),
+                rook: svg(include_bytes!("merida/wR.svg"), "merida/wR.svg"),
+                queen: svg(include_bytes!("merida/wQ.svg"), "merida/wQ.svg"),
+                king: svg(include_bytes!("merida/wK.svg"), "merida/wK.svg"),
+            },
+            scale: 1.0 / 177.0,
+            #[cfg(feature = "font-pieces")]
+            font: None,
+        }
+    }
+
+    /// The built-in cburnett piece set, as seen on lichess.
+    pub fn cburnett() -> PieceSet {
+        PieceSet {
+            black: PieceSetSide {
+                pawn: svg(include_bytes!("cburnett/bP.svg"), "cburnett/bP.svg"),
+                knight: svg(include_bytes!("cburnett/bN.svg"), "cburnett/bN.svg"),
+                bishop: svg(include_bytes!("cburnett/bB.svg"), "cburnett/bB.svg"),
+                rook: svg(include_bytes!("cburnett/bR.svg"), "cburnett/bR.svg"),
+                queen: svg(include_bytes!("cburnett/bQ.svg"), "cburnett/bQ.svg"),
+                king: svg(include_bytes!("cburnett/bK.svg"), "cburnett/bK.svg"),
             },
             white: PieceSetSide {
-                pawn: Handle::from_data(include_bytes!("merida/wP.svg")).expect("merida/wP.svg"),
-                knight: Handle::from_data(include_bytes!("merida/wN.svg")).expect("merida/wN.svg"),
-                bishop: Handle::from_data(include_bytes!("merida/wB.svg")).expect("merida/wB.svg"),
-                rook: Handle::from_data(include_bytes!("merida/wR.svg")).expect("merida/wR.svg"),
-                queen: Handle::from_data(include_bytes!("merida/wQ.svg")).expect("merida/wQ.svg"),
-                king: Handle::from_data(include_bytes!("merida/wK.svg")).expect("merida/wK.svg"),
+                pawn: svg(include_bytes!("cburnett/wP.svg"), "cburnett/wP.svg"),
+                knight: svg(include_bytes!("cburnett/wN.svg"), "cburnett/wN.svg"),
+                bishop: svg(include_bytes!("cburnett/wB.svg"), "cburnett/wB.svg"),
+                rook: svg(include_bytes!("cburnett/wR.svg"), "cburnett/wR.svg"),
+                queen: svg(include_bytes!("cburnett/wQ.svg"), "cburnett/wQ.svg"),
+                king: svg(include_bytes!("cburnett/wK.svg"), "cburnett/wK.svg"),
             },
+            scale: 1.0 / 45.0,
+            #[cfg(feature = "font-pieces")]
+            font: None,
+        }
+    }
+}
+
+#[cfg(feature = "font-pieces")]
+impl PieceSet {
+    /// Draw the pieces with the Unicode chess glyphs of the given font
+    /// family, without any SVG images. `size` is the font size relative
+    /// to the squares, for example `0.9`.
+    ///
+    /// The solid glyphs are used for both sides, filled in white or
+    /// black, because the outlined glyphs of many fonts are not opaque.
+    pub fn from_font(family: &str, size: f64) -> PieceSet {
+        let side = || PieceSetSide {
+            pawn: PieceImage::Glyph('\u{265f}'),
+            knight: PieceImage::Glyph('\u{265e}'),
+            bishop: PieceImage::Glyph('\u{265d}'),
+            rook: PieceImage::Glyph('\u{265c}'),
+            queen: PieceImage::Glyph('\u{265b}'),
+            king: PieceImage::Glyph('\u{265a}'),
+        };
+
+        PieceSet {
+            black: side(),
+            white: side(),
+            scale: size,
+            font: Some(family.to_owned()),
+        }
+    }
+}
+
+#[cfg(feature = "svg-pieces")]
+impl PieceSet {
+    /// Load a piece set from a directory with one SVG file per piece,
+    /// named like `wP.svg`, `wN.svg`, ..., `bK.svg`.
+    ///
+    /// Pieces that are missing or can not be loaded are replaced with
+    /// the corresponding merida piece, with a warning on standard error,
+    /// so that partial sets are still usable.
+    ///
+    /// All images should have the same size. The pieces are scaled to
+    /// the squares by the width of the white king.
+    pub fn from_directory<P: AsRef<Path>>(dir: P) -> PieceSet {
+        let dir = dir.as_ref();
+        let merida = PieceSet::merida();
+
+        let load = |name: &str, fallback: &PieceImage| {
+            let path = dir.join(format!("{}.svg", name));
+            let result = fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|data| Handle::from_data(&data).map_err(|err| err.to_string()));
+
+            result.map(PieceImage::Svg).unwrap_or_else(|err| {
+                eprintln!("chessground: could not load {}: {}, using merida piece instead", path.display(), err);
+                fallback.clone()
+            })
+        };
+
+        let side = |prefix: &str, fallback: &PieceSetSide| PieceSetSide {
+            pawn: load(&format!("{}P", prefix), &fallback.pawn),
+            knight: load(&format!("{}N", prefix), &fallback.knight),
+            bishop: load(&format!("{}B", prefix), &fallback.bishop),
+            rook: load(&format!("{}R", prefix), &fallback.rook),
+            queen: load(&format!("{}Q", prefix), &fallback.queen),
+            king: load(&format!("{}K", prefix), &fallback.king),
+        };
+
+        let white = side("w", &merida.white);
+        let black = side("b", &merida.black);
+        let width = match white.king {
+            PieceImage::Svg(ref handle) => handle.get_dimensions().width,
+            #[cfg(feature = "font-pieces")]
+            PieceImage::Glyph(_) => 1,
+        };
+        let scale = 1.0 / f64::from(width.max(1));
+
+        PieceSet {
+            black,
+            white,
+            scale,
+            #[cfg(feature = "font-pieces")]
+            font: None,
         }
     }
 }
@@ -16,11 +16,10 @@
 
 use std::f64::consts::PI;
 
-use time::SteadyTime;
+use time::{Duration, SteadyTime};
 
 use gtk::prelude::*;
 use cairo::Context;
-use rsvg::HandleExt;
 
 use shakmaty::{Square, Rank, Color, Role, MoveList};
 
@@ -29,17 +28,22 @@ use pieces::Pieces;
 use boardstate::BoardState;
 use ground::{WidgetContext, EventContext, GroundMsg};
 
+#[derive(Clone)]
 pub struct Promotable {
     promoting: Option<Promoting>,
+    tap_to_queen: bool,
 }
 
+#[derive(Clone)]
 struct Promoting {
     color: Color,
     orig: Square,
     dest: Square,
+    since: SteadyTime,
     hover: Option<Hover>,
 }
 
+#[derive(Clone)]
 struct Hover {
     square: Square,
     since: SteadyTime,
@@ -50,27 +54,39 @@ impl Promotable {
     pub fn new() -> Promotable {
         Promotable {
             promoting: None,
+            tap_to_queen: false,
         }
     }
 
-    pub fn start(&mut self, color: Color, orig: Square, dest: Square) {
+    pub fn set_tap_to_queen(&mut self, tap_to_queen: bool) {
+        self.tap_to_queen = tap_to_queen;
+    }
+
+    pub fn start(&mut self, color: Color, orig: Square, dest: Square, now: SteadyTime) {
         self.promoting = Some(Promoting {
             color,
             orig,
             dest,
+            since: now,
             hover: Some(Hover {
                 square: dest,
-                since: SteadyTime::now(),
+                since: now,
                 elapsed: 0.0,
             }),
         });
     }
 
-    pub fn cancel(&mut self) {
-        self.promoting = None;
+    pub fn settle(&mut self) {
+        if let Some(Promoting { hover: Some(ref mut hover), .. }) = self.promoting {
+            hover.elapsed = 1.0;
+        }
+    }
+
+    pub fn cancel(&mut self) -> bool {
+        self.promoting.take().is_some()
     }
 
-    pub fn update(&mut self, legals: &MoveList) {
+    pub fn update(&mut self, legals: &MoveList) -> bool {
         let cancel = if let Some(ref promoting) = self.promoting {
             !legals.iter().any(|m| {
                 m.from() == Some(promoting.orig) && m.to() == promoting.dest &&
@@ -80,9 +96,7 @@ impl Promotable {
             false
         };
 
-        if cancel {
-            self.cancel();
-        }
+        cancel && self.cancel()
     }
 
     pub fn is_promoting(&self, orig: Square) -> bool {
@@ -95,7 +109,7 @@ impl Promotable {
                 ctx.queue_draw_square(hover.square);
             }
 
-            hover.elapsed = ((SteadyTime::now() - hover.since).num_milliseconds() as f64 / 1000.0).min(1.0);
+            hover.elapsed = ((ctx.now() - hover.since).num_milliseconds() as f64 / 1000.0).min(1.0);
         }
     }
 
@@ -114,7 +128,7 @@ impl Promotable {
 
                 promoting.hover = square.map(|square| Hover {
                     square,
-                    since: SteadyTime::now(),
+                    since: ctx.now(),
                     elapsed: 0.0,
                 });
             }
@@ -122,12 +136,30 @@ impl Promotable {
     }
 
     pub(crate) fn mouse_down(&mut self, pieces: &mut Pieces, ctx: &EventContext) -> Inhibit {
+        if self.tap_to_queen {
+            if let Some(ref promoting) = self.promoting {
+                // a quick second tap meant for the queen on the destination
+                // square easily hits one of the underpromotions instead
+                let hasty = ctx.now() - promoting.since < Duration::milliseconds(400);
+                let underpromotion = ctx.square().map_or(false, |sq| {
+                    // rook, bishop and knight below the queen
+                    let offset = promoting.orientation().fold_wb(-1, 1) *
+                                 (i8::from(sq.rank()) - i8::from(promoting.dest.rank()));
+                    sq.file() == promoting.dest.file() && (1..4).contains(&offset)
+                });
+                if hasty && underpromotion {
+                    return Inhibit(true);
+                }
+            }
+        }
+
         if let Some(promoting) = self.promoting.take() {
             ctx.widget().queue_draw();
 
+            let duration = pieces.animation_duration();
             if let Some(figurine) = pieces.figurine_at_mut(promoting.orig) {
                 // animate the figurine when cancelling
-                figurine.set_pos(square_to_pos(promoting.dest));
+                figurine.set_pos(square_to_pos(promoting.dest), ctx.now(), duration);
             }
 
             if let Some(square) = ctx.square() {
@@ -151,6 +183,8 @@ impl Promotable {
                     }
                 }
             }
+
+            ctx.stream().emit(GroundMsg::PromotionCancelled);
         }
 
         Inhibit(false)
@@ -216,10 +250,9 @@ impl Promoting {
 
             cr.translate(0.5 + file_to_float(self.dest.file()), 7.5 - f64::from(rank));
             cr.scale(2f64.sqrt() * radius, 2f64.sqrt() * radius);
-            cr.rotate(state.orientation().fold_wb(0.0, PI));
+            cr.rotate(state.upright());
             cr.translate(-0.5, -0.5);
-            cr.scale(state.piece_set().scale(), state.piece_set().scale());
-            state.piece_set().by_piece(&role.of(self.color)).render_cairo(cr);
+            state.draw_piece(cr, &role.of(self.color))?;
 
             cr.restore()?;
         }
@@ -27,6 +27,17 @@ pub fn ease(start: f64, end: f64, t: f64) -> f64 {
     start + (end - start) * ease
 }
 
+/// Contrast ratio between two colors, from 1 to 21, as defined by WCAG.
+pub fn contrast(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    fn luminance((r, g, b): (f64, f64, f64)) -> f64 {
+        let linear = |c: f64| if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+    }
+
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
 pub fn pos_to_square((x, y): (f64, f64)) -> Option<Square> {
     let (x, y) = (x.floor(), y.floor());
     if 0f64 <= x && x <= 7f64 && 0f64 <= y && y <= 7f64 {